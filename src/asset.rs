@@ -0,0 +1,67 @@
+use crate::color::ColorSpace;
+use crate::texture::{self, MipLevel};
+use image::DynamicImage;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+//Images uploaded once through PUT /assets/{name} and shared by every scene that names them.
+//They are decoded on upload so a render only pays for a hash map lookup.
+#[derive(Default)]
+pub struct AssetStore {
+    images: RwLock<HashMap<String, Arc<Asset>>>,
+}
+
+//An uploaded image and the mip chains trilinear textures have built from it, one per color space it was read in.
+//Uploading over a name replaces the whole asset, so scenes never get mips built from the image it replaced.
+pub struct Asset {
+    pub image: Arc<DynamicImage>,
+    mips: Mutex<HashMap<ColorSpace, Arc<Vec<MipLevel>>>>,
+}
+
+impl Asset {
+    //Built by the first render that asks and shared by all later ones
+    pub fn mips(&self, color_space: ColorSpace) -> Arc<Vec<MipLevel>> {
+        self.mips.lock().unwrap()
+            .entry(color_space)
+            .or_insert_with(|| Arc::new(texture::build_mips(&self.image, color_space)))
+            .clone()
+    }
+}
+
+impl AssetStore {
+    pub fn new() -> AssetStore {
+        AssetStore::default()
+    }
+
+    pub fn insert(&self, name: &str, bytes: &[u8]) -> Result<(), String> {
+        let image = image::load_from_memory(bytes).map_err(|e| format!("asset {} could not be decoded: {}", name, e))?;
+        let asset = Asset { image: Arc::new(image), mips: Mutex::new(HashMap::new()) };
+        self.images.write().unwrap().insert(name.to_string(), Arc::new(asset));
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Asset>> {
+        self.images.read().unwrap().get(name).cloned()
+    }
+}
+
+thread_local! {
+    //serde gives us no way to hand the store to the deserializers, so it is made visible to them
+    //for the duration of a single (synchronous) deserialize call through with_store
+    static CURRENT: RefCell<Option<Arc<AssetStore>>> = const { RefCell::new(None) };
+}
+
+pub fn with_store<T, F: FnOnce() -> T>(store: &Arc<AssetStore>, f: F) -> T {
+    let previous = CURRENT.with(|c| c.replace(Some(store.clone())));
+    let result = f();
+    CURRENT.with(|c| c.replace(previous));
+    result
+}
+
+pub fn lookup(name: &str) -> Result<Arc<Asset>, String> {
+    CURRENT.with(|c| match &*c.borrow() {
+        Some(store) => store.get(name).ok_or_else(|| format!("unknown asset: {}", name)),
+        None => Err(format!("asset {} referenced but no asset store is available", name)),
+    })
+}
@@ -0,0 +1,160 @@
+use crate::vector3::Vector3;
+use crate::material::Material;
+use crate::transform::Transform;
+use crate::mesh::Mesh;
+use serde::{Deserialize, Deserializer};
+use serde::de::Error;
+
+const MAX_DIVISIONS: usize = 64; //per side of a patch
+
+//Bicubic Bézier patches written the way patch models such as the Utah teapot are usually shared: one list of
+//control points and, for each patch, the 16 (0 based) indices of its points row by row e.g.
+//{ "Patches": { "vertices": [ ... ], "patches": [ [0, 1, 2, 3, 4, ...], ... ], "tolerance": 0.01, "material": ... } }.
+//Each patch is cut into a grid of quads when the scene is read, finer where it curves more: no point of the
+//triangles is further than tolerance from the surface (a thousandth of the size of the whole model unless given).
+//Normals and texture coords (the patch's own u and v) are taken from the exact surface. Like a quad, a patch faces
+//the way its slope along a row (u) crossed with its slope down the rows (v) points.
+#[derive(Deserialize)]
+struct PatchesDesc {
+    vertices: Vec<Vector3>,
+    patches: Vec<Vec<usize>>,
+    #[serde(default)]
+    tolerance: Option<f64>,
+    material: Material,
+    #[serde(default)]
+    transform: Option<Transform>,
+}
+
+pub fn patches<'de, D>(deserializer: D) -> Result<Mesh, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let desc = PatchesDesc::deserialize(deserializer)?;
+    tessellate(desc).map_err(D::Error::custom)
+}
+
+//Patches that share an edge cut it the same way, as the number of pieces an edge is cut into depends only on its own
+//four control points. A patch that needs more rows or columns than one of its edges lines their ends up along that
+//edge's pieces, so there are no cracks between neighbours tessellated more and less finely.
+fn tessellate(desc: PatchesDesc) -> Result<Mesh, String> {
+    let tolerance = match desc.tolerance {
+        Some(t) if t > 0.0 => t,
+        Some(_) => return Err(String::from("patch tolerance must be positive")),
+        None => model_size(&desc.vertices) * 1e-3,
+    };
+    let (mut positions, mut normals, mut uvs, mut triangles) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for indices in &desc.patches {
+        if indices.len() != 16 {
+            return Err(format!("a bicubic patch needs 16 control points, not {}", indices.len()));
+        }
+        let mut points = [[Vector3::zero(); 4]; 4];
+        for (k, &index) in indices.iter().enumerate() {
+            points[k / 4][k % 4] = *desc.vertices.get(index).ok_or_else(|| format!("patch control point {} does not exist", index))?;
+        }
+        let column_of = |c: usize| [points[0][c], points[1][c], points[2][c], points[3][c]];
+        let edges = [points[0], points[3], column_of(0), column_of(3)]; //v = 0, v = 1, u = 0 and u = 1
+        let pieces: Vec<usize> = edges.iter().map(|edge| divisions(&[*edge], tolerance)).collect();
+        let across = [column_of(0), column_of(1), column_of(2), column_of(3)];
+        let columns = divisions(&points, tolerance).max(pieces[0]).max(pieces[1]);
+        let rows = divisions(&across, tolerance).max(pieces[2]).max(pieces[3]);
+
+        let first = positions.len();
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let (u, v) = (column as f64 / columns as f64, row as f64 / rows as f64);
+                let position = if row == 0 || row == rows {
+                    on_edge(&edges[if row == 0 { 0 } else { 1 }], pieces[if row == 0 { 0 } else { 1 }], u)
+                } else if column == 0 || column == columns {
+                    on_edge(&edges[if column == 0 { 2 } else { 3 }], pieces[if column == 0 { 2 } else { 3 }], v)
+                } else {
+                    evaluate(&points, u, v)
+                };
+                positions.push(position);
+                normals.push(normal(&points, u, v));
+                uvs.push((u, v));
+            }
+        }
+        let at = |row: usize, column: usize| first + row * (columns + 1) + column;
+        for row in 0..rows {
+            for column in 0..columns {
+                triangles.push([at(row, column), at(row, column + 1), at(row + 1, column + 1)]);
+                triangles.push([at(row, column), at(row + 1, column + 1), at(row + 1, column)]);
+            }
+        }
+    }
+    Mesh::new(positions, normals, uvs, triangles, desc.material, desc.transform)
+}
+
+fn model_size(vertices: &[Vector3]) -> f64 {
+    let first = vertices.first().cloned().unwrap_or_else(Vector3::zero);
+    let (min, max) = vertices.iter().fold((first, first), |(min, max), p| {
+        (Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)), Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)))
+    });
+    (max - min).length()
+}
+
+//How many pieces cubic curves must each be cut into (all alike) to stay within tolerance of them. A piece's chord
+//strays from its stretch of a cubic by at most 3 * 2 / 8 times the largest second difference of the control points
+//over the square of the number of pieces
+fn divisions(curves: &[[Vector3; 4]], tolerance: f64) -> usize {
+    let bend = curves.iter()
+        .flat_map(|c| vec![c[0] - c[1] * 2.0 + c[2], c[1] - c[2] * 2.0 + c[3]])
+        .map(|d| d.length())
+        .fold(0.0, f64::max);
+    let pieces = (0.75 * bend / tolerance).sqrt().ceil();
+    if pieces.is_finite() { (pieces as usize).clamp(1, MAX_DIVISIONS) } else { MAX_DIVISIONS }
+}
+
+//The point at t on a cubic curve cut into pieces straight pieces
+fn on_edge(curve: &[Vector3; 4], pieces: usize, t: f64) -> Vector3 {
+    let scaled = t * pieces as f64;
+    let piece = (scaled.floor() as usize).min(pieces - 1);
+    let along = scaled - piece as f64;
+    let point = |t: f64| {
+        let b = bernstein(t);
+        curve[0] * b[0] + curve[1] * b[1] + curve[2] * b[2] + curve[3] * b[3]
+    };
+    let (start, end) = (point(piece as f64 / pieces as f64), point((piece + 1) as f64 / pieces as f64));
+    start + (end - start) * along
+}
+
+fn bernstein(t: f64) -> [f64; 4] {
+    let s = 1.0 - t;
+    [s * s * s, 3.0 * s * s * t, 3.0 * s * t * t, t * t * t]
+}
+
+fn bernstein_derivative(t: f64) -> [f64; 4] {
+    let s = 1.0 - t;
+    [-3.0 * s * s, 3.0 * s * s - 6.0 * s * t, 6.0 * s * t - 3.0 * t * t, 3.0 * t * t]
+}
+
+fn combine(points: &[[Vector3; 4]; 4], weights_v: [f64; 4], weights_u: [f64; 4]) -> Vector3 {
+    let mut sum = Vector3::zero();
+    for row in 0..4 {
+        for column in 0..4 {
+            sum = sum + points[row][column] * (weights_v[row] * weights_u[column]);
+        }
+    }
+    sum
+}
+
+fn evaluate(points: &[[Vector3; 4]; 4], u: f64, v: f64) -> Vector3 {
+    combine(points, bernstein(v), bernstein(u))
+}
+
+//The cross product of the surface's slopes along u and v. Where a whole edge of control points meets in one point,
+//as at the top of the teapot's lid, one slope vanishes, so the normal is taken from just inside the patch instead.
+fn normal(points: &[[Vector3; 4]; 4], u: f64, v: f64) -> Vector3 {
+    let slopes = |u: f64, v: f64| {
+        let du = combine(points, bernstein(v), bernstein_derivative(u));
+        let dv = combine(points, bernstein_derivative(v), bernstein(u));
+        du.cross(&dv)
+    };
+    let n = slopes(u, v);
+    if n.length() > 1e-12 {
+        return n.normalize();
+    }
+    let inside = |t: f64| t.clamp(1e-4, 1.0 - 1e-4);
+    let n = slopes(inside(u), inside(v));
+    if n.length() > 1e-12 { n.normalize() } else { Vector3::new(0.0, 1.0, 0.0) }
+}
@@ -0,0 +1,232 @@
+use crate::transform::Transform;
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+//Where the scene is seen from. Without a transform the camera sits at the origin looking down -z with y up;
+//a transform moves and turns it from there, and one with an end moves it while the shutter is open e.g.
+//"camera": { "transform": { "translate": { ... }, "end": { "translate": { ... } } }, "shutter": { "open": 0, "close": 0.5 } }.
+//With an aperture it is a thin lens instead of a pinhole: only things focus_distance in front of it are sharp, and
+//each sample looks through a different point of the lens, so many samples per pixel are needed for smooth blur.
+//A lens has nothing sensible to focus on by default, so an aperture needs a focus_distance to go with it.
+//The aperture is round unless it has blades (at least 3), which make it and out of focus highlights a polygon
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "CameraDesc")]
+pub struct Camera {
+    pub transform: Option<Transform>,
+    pub shutter: Shutter,
+    pub aperture: f64, //radius of the lens, 0 for a pinhole
+    pub focus_distance: f64, //along the direction the camera looks
+    pub blades: u32,
+    pub blade_rotation: f64, //degrees
+}
+
+#[derive(Deserialize)]
+struct CameraDesc {
+    #[serde(default)]
+    transform: Option<Transform>,
+    #[serde(default)]
+    shutter: Shutter,
+    #[serde(default)]
+    aperture: f64,
+    #[serde(default)]
+    focus_distance: Option<f64>,
+    #[serde(default)]
+    blades: u32,
+    #[serde(default)]
+    blade_rotation: f64,
+}
+
+fn default_focus_distance() -> f64 {
+    1.0 //the plane the field of view is measured on; a pinhole is sharp at any distance
+}
+
+impl TryFrom<CameraDesc> for Camera {
+    type Error = String;
+
+    fn try_from(desc: CameraDesc) -> Result<Self, Self::Error> {
+        if desc.aperture < 0.0 {
+            return Err(String::from("a camera aperture cannot be negative"));
+        }
+        let focus_distance = match desc.focus_distance {
+            Some(distance) if distance <= 0.0 => return Err(String::from("a camera focus_distance must be positive")),
+            Some(distance) => distance,
+            None if desc.aperture > 0.0 => return Err(String::from("a camera with an aperture needs a focus_distance")),
+            None => default_focus_distance(),
+        };
+        Ok(Camera {
+            transform: desc.transform,
+            shutter: desc.shutter,
+            aperture: desc.aperture,
+            focus_distance,
+            blades: desc.blades,
+            blade_rotation: desc.blade_rotation,
+        })
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            transform: None,
+            shutter: Shutter::default(),
+            aperture: 0.0,
+            focus_distance: default_focus_distance(),
+            blades: 0,
+            blade_rotation: 0.0,
+        }
+    }
+}
+
+//The part of the frame (0 is its start, 1 its end) that the shutter is open for. Moving things are caught
+//somewhere in it by each sample, so with several samples per pixel they blur along their path. It has to lie
+//within the frame, since outside of it keyframes would be extrapolated (and could e.g. scale things to nothing)
+#[derive(Clone, Copy, Deserialize)]
+#[serde(try_from = "ShutterDesc")]
+pub struct Shutter {
+    pub open: f64,
+    pub close: f64,
+}
+
+#[derive(Deserialize)]
+struct ShutterDesc {
+    open: f64,
+    close: f64,
+}
+
+impl TryFrom<ShutterDesc> for Shutter {
+    type Error = String;
+
+    fn try_from(desc: ShutterDesc) -> Result<Self, Self::Error> {
+        if !(0.0 <= desc.open && desc.open <= desc.close && desc.close <= 1.0) {
+            return Err(format!("a shutter must open and close within the frame (0 <= open <= close <= 1), not {} to {}", desc.open, desc.close));
+        }
+        Ok(Shutter { open: desc.open, close: desc.close })
+    }
+}
+
+impl Default for Shutter {
+    fn default() -> Self {
+        Shutter { open: 0.0, close: 1.0 }
+    }
+}
+
+impl Shutter {
+    //Sample times are spread evenly over the interval, each jittered within its own stretch of it
+    pub fn time(&self, x: u32, y: u32, sample: u32, samples: u32) -> f64 {
+        let offset = (sample as f64 + jitter(x, y, sample, 0)) / samples as f64;
+        self.open + (self.close - self.open) * offset
+    }
+}
+
+impl Camera {
+    //The point of the lens a sample looks through, relative to its center in the camera's own x and y
+    pub fn lens_point(&self, x: u32, y: u32, sample: u32) -> (f64, f64) {
+        if self.aperture <= 0.0 {
+            return (0.0, 0.0);
+        }
+        let (u, v) = (jitter(x, y, sample, 1), jitter(x, y, sample, 2));
+        let (px, py) = if self.blades >= 3 { polygon_point(self.blades, self.blade_rotation, u, v) } else { disk_point(u, v) };
+        (px * self.aperture, py * self.aperture)
+    }
+}
+
+//Shirley's concentric mapping of the unit square onto the unit disk, which keeps evenly spread samples evenly spread
+fn disk_point(u: f64, v: f64) -> (f64, f64) {
+    let (a, b) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    let quarter = std::f64::consts::FRAC_PI_4;
+    let (radius, angle) = if a.abs() > b.abs() { (a, quarter * (b / a)) } else { (b, 2.0 * quarter - quarter * (a / b)) };
+    (radius * angle.cos(), radius * angle.sin())
+}
+
+//Uniformly in a regular polygon with its corners on the unit circle: u picks one of the triangles between the
+//center and two neighbouring corners (and is then reused as a fresh number), v and u place the point in it
+fn polygon_point(blades: u32, rotation: f64, u: f64, v: f64) -> (f64, f64) {
+    let scaled = u * blades as f64;
+    let blade = scaled.floor().min(blades as f64 - 1.0);
+    let u = scaled - blade;
+    let corner = |k: f64| {
+        let angle = rotation.to_radians() + k * 2.0 * std::f64::consts::PI / blades as f64;
+        (angle.cos(), angle.sin())
+    };
+    let (a, b) = (corner(blade), corner(blade + 1.0));
+    let root = v.sqrt(); //so that the points do not bunch up at the center
+    let (wa, wb) = (root * (1.0 - u), root * u);
+    (a.0 * wa + b.0 * wb, a.1 * wa + b.1 * wb)
+}
+
+//A number in [0, 1) that looks random but is the same every time for a pixel, sample and dimension (what the
+//number is used for), so renders can be repeated exactly
+pub fn jitter(x: u32, y: u32, sample: u32, dimension: u32) -> f64 {
+    let mut h = x.wrapping_mul(0x8da6_b343) ^ y.wrapping_mul(0xd816_3841) ^ sample.wrapping_mul(0xcb1a_b31f) ^ dimension.wrapping_mul(0x9e37_79b9);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^= h >> 16;
+    h as f64 / 4_294_967_296.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid() -> Vec<(f64, f64)> {
+        (0..=20).flat_map(|i| (0..=20).map(move |j| (i as f64 / 20.0, j as f64 / 20.0))).collect()
+    }
+
+    #[test]
+    fn disk_points_fill_the_unit_disk() {
+        assert_eq!(disk_point(0.5, 0.5), (0.0, 0.0));
+        let (x, y) = disk_point(1.0, 0.5);
+        assert!((x - 1.0).abs() < 1e-9 && y.abs() < 1e-9);
+        let (x, y) = disk_point(0.5, 1.0);
+        assert!(x.abs() < 1e-9 && (y - 1.0).abs() < 1e-9);
+        assert!(grid().into_iter().all(|(u, v)| { let (x, y) = disk_point(u, v); x * x + y * y <= 1.0 + 1e-9 }));
+    }
+
+    #[test]
+    fn polygon_points_stay_inside_the_blades() {
+        let lenses: [(u32, f64); 3] = [(3, 0.0), (5, 18.0), (6, 30.0)];
+        for &(blades, rotation) in lenses.iter() {
+            let corners: Vec<(f64, f64)> = (0..=blades).map(|k| {
+                let angle = rotation.to_radians() + k as f64 * 2.0 * std::f64::consts::PI / blades as f64;
+                (angle.cos(), angle.sin())
+            }).collect();
+            for (u, v) in grid() {
+                let (x, y) = polygon_point(blades, rotation, u, v);
+                for edge in corners.windows(2) { //corners run counter clockwise, so the inside is on the left of every edge
+                    let ((ax, ay), (bx, by)) = (edge[0], edge[1]);
+                    assert!((bx - ax) * (y - ay) - (by - ay) * (x - ax) >= -1e-9, "{} blades at {}, {}", blades, u, v);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shutter_times_stay_in_their_own_stretch() {
+        let shutter = Shutter { open: 0.25, close: 0.75 };
+        for sample in 0..8 {
+            let time = shutter.time(3, 5, sample, 8);
+            let stretch = 0.25 + 0.5 * sample as f64 / 8.0;
+            assert!(time >= stretch && time < stretch + 0.5 / 8.0, "sample {} at {}", sample, time);
+        }
+    }
+
+    #[test]
+    fn shutter_outside_the_frame_is_rejected() {
+        let parse = |json: &str| serde_json::from_str::<Shutter>(json);
+        assert!(parse(r#"{ "open": 0.2, "close": 0.6 }"#).is_ok());
+        assert!(parse(r#"{ "open": -1, "close": -1 }"#).is_err());
+        assert!(parse(r#"{ "open": 0.5, "close": 1.5 }"#).is_err());
+        assert!(parse(r#"{ "open": 0.6, "close": 0.2 }"#).is_err());
+    }
+
+    #[test]
+    fn closed_shutter_gives_one_time() {
+        let shutter = Shutter { open: 0.5, close: 0.5 };
+        assert!((0..4).all(|sample| shutter.time(1, 2, sample, 4) == 0.5));
+    }
+}
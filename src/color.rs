@@ -1,16 +1,95 @@
 // use image;
 use std::ops::{Mul, Add};
-use image::{Pixel, Rgba};
+use image::{Pixel, Rgb, Rgba};
 use serde::{Serialize, Deserialize};
 
-const GAMMA: f32 = 2.2;
+fn srgb_encode(linear: f32) -> f32 { //piecewise sRGB curve: a linear toe near black, then a 2.4 power segment
+    if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn srgb_decode(encoded: f32) -> f32 {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn rec709_encode(linear: f32) -> f32 { //BT.709 OETF
+    if linear < 0.018 {
+        linear * 4.5
+    } else {
+        1.099 * linear.powf(0.45) - 0.099
+    }
+}
+
+fn rec709_decode(encoded: f32) -> f32 {
+    if encoded < 0.081 {
+        encoded / 4.5
+    } else {
+        ((encoded + 0.099) / 1.099).powf(1.0 / 0.45)
+    }
+}
+
+//Linear sRGB (Rec.709 primaries, D65) <-> linear Display P3 (DCI-P3 primaries, D65)
+const SRGB_TO_P3: [[f32; 3]; 3] = [
+    [0.8224621, 0.177538, 0.0000000],
+    [0.0331941, 0.9668058, 0.0000000],
+    [0.0170827, 0.0723974, 0.9105199],
+];
+
+const P3_TO_SRGB: [[f32; 3]; 3] = [
+    [1.2249401, -0.2249404, 0.0000000],
+    [-0.0420569, 1.0420571, 0.0000000],
+    [-0.0196376, -0.0786361, 1.0982735],
+];
 
-fn gamma_encode(linear: f32) -> f32 {
-    linear.powf(1.0 / GAMMA)
+//The tracer works in linear light with sRGB/Rec.709 primaries. A ColorSpace describes how those values are
+//written to (or read from) 8 bit images: which primaries the channels refer to and which transfer curve encodes them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    Linear,
+    Rec709,
+    DisplayP3,
 }
 
-fn gamma_decode(encoded: f32) -> f32 {
-    encoded.powf(GAMMA)
+impl ColorSpace {
+    pub fn encode(&self, linear: f32) -> f32 {
+        match *self {
+            ColorSpace::Srgb | ColorSpace::DisplayP3 => srgb_encode(linear),
+            ColorSpace::Linear => linear,
+            ColorSpace::Rec709 => rec709_encode(linear),
+        }
+    }
+
+    pub fn decode(&self, encoded: f32) -> f32 {
+        match *self {
+            ColorSpace::Srgb | ColorSpace::DisplayP3 => srgb_decode(encoded),
+            ColorSpace::Linear => encoded,
+            ColorSpace::Rec709 => rec709_decode(encoded),
+        }
+    }
+
+    //converts a linear color in the working space into linear values on this space's primaries
+    pub fn to_primaries(self, color: Color) -> Color {
+        match self {
+            ColorSpace::DisplayP3 => color.transform(&SRGB_TO_P3),
+            _ => color,
+        }
+    }
+
+    pub fn to_working(self, color: Color) -> Color {
+        match self {
+            ColorSpace::DisplayP3 => color.transform(&P3_TO_SRGB),
+            _ => color,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, Copy)]
@@ -31,19 +110,66 @@ impl Color {
     }
 
     pub fn to_rgba(&self) -> Rgba<u8> {
+        self.to_rgba_in(ColorSpace::Srgb)
+    }
+
+    pub fn to_rgba_in(self, color_space: ColorSpace) -> Rgba<u8> {
+        let c = self.encode(color_space);
         Rgba::from_channels(
-            (gamma_encode(self.red) * 255.0) as u8,
-            (gamma_encode(self.green) * 255.0) as u8,
-            (gamma_encode(self.blue) * 255.0) as u8,
+            (c.red * 255.0) as u8,
+            (c.green * 255.0) as u8,
+            (c.blue * 255.0) as u8,
             255,
         )
     }
 
+    pub fn to_rgb16_in(self, color_space: ColorSpace) -> Rgb<u16> {
+        let c = self.encode(color_space);
+        Rgb([
+            (c.red * 65535.0) as u16,
+            (c.green * 65535.0) as u16,
+            (c.blue * 65535.0) as u16,
+        ])
+    }
+
+    fn encode(&self, color_space: ColorSpace) -> Color { //gamut conversion happens before clamping so out of gamut colors clip in the output space
+        let c = color_space.to_primaries(*self).clamp();
+        Color {
+            red: color_space.encode(c.red),
+            green: color_space.encode(c.green),
+            blue: color_space.encode(c.blue),
+        }
+    }
+
     pub fn from_rgba(rgba: Rgba<u8>) -> Color {
+        Color::from_rgba_in(rgba, ColorSpace::Srgb)
+    }
+
+    pub fn from_rgba_in(rgba: Rgba<u8>, color_space: ColorSpace) -> Color {
+        color_space.to_working(Color {
+            red: color_space.decode((rgba[0] as f32) / 255.0),
+            green: color_space.decode((rgba[1] as f32) / 255.0),
+            blue: color_space.decode((rgba[2] as f32) / 255.0),
+        })
+    }
+
+    pub fn transmittance(&self, distance: f32) -> Color { //treating self as an absorption coefficient, how much of each channel survives the distance
         Color {
-            red: gamma_decode((rgba[0] as f32) / 255.0),
-            green: gamma_decode((rgba[1] as f32) / 255.0),
-            blue: gamma_decode((rgba[2] as f32) / 255.0),
+            red: (-self.red * distance).exp(),
+            green: (-self.green * distance).exp(),
+            blue: (-self.blue * distance).exp(),
+        }
+    }
+
+    pub fn luminance(&self) -> f32 { //relative luminance of a linear sRGB color
+        0.2126 * self.red + 0.7152 * self.green + 0.0722 * self.blue
+    }
+
+    fn transform(&self, m: &[[f32; 3]; 3]) -> Color {
+        Color {
+            red: m[0][0] * self.red + m[0][1] * self.green + m[0][2] * self.blue,
+            green: m[1][0] * self.red + m[1][1] * self.green + m[1][2] * self.blue,
+            blue: m[2][0] * self.red + m[2][1] * self.green + m[2][2] * self.blue,
         }
     }
 }
@@ -94,4 +220,38 @@ impl Add for Color {
 //             z: self.z * rhs
 //         }
 //     }
-// }
\ No newline at end of file
+// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPACES: [ColorSpace; 4] = [ColorSpace::Srgb, ColorSpace::Linear, ColorSpace::Rec709, ColorSpace::DisplayP3];
+
+    #[test]
+    fn transfer_curves_round_trip() {
+        for &space in SPACES.iter() {
+            for i in 0..=100 {
+                let linear = i as f32 / 100.0;
+                assert!((space.decode(space.encode(linear)) - linear).abs() < 1e-5, "{:?} at {}", space, linear);
+            }
+            assert!(space.encode(0.0).abs() < 1e-6 && (space.encode(1.0) - 1.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn srgb_curve_has_a_linear_toe() {
+        assert!((ColorSpace::Srgb.encode(0.002) - 0.002 * 12.92).abs() < 1e-7);
+        assert!((ColorSpace::Srgb.decode(0.5) - 0.21404).abs() < 1e-4); //not 0.5^2.2 = 0.2176
+    }
+
+    #[test]
+    fn display_p3_keeps_white_and_round_trips() {
+        let white = ColorSpace::DisplayP3.to_primaries(Color { red: 1.0, green: 1.0, blue: 1.0 });
+        assert!((white.red - 1.0).abs() < 1e-5 && (white.green - 1.0).abs() < 1e-5 && (white.blue - 1.0).abs() < 1e-5);
+        let red = Color { red: 1.0, green: 0.0, blue: 0.0 };
+        let p3 = ColorSpace::DisplayP3.to_primaries(red);
+        assert!(p3.red < 1.0 && p3.green > 0.0); //sRGB red sits inside the wider P3 gamut
+        let back = ColorSpace::DisplayP3.to_working(p3);
+        assert!((back.red - 1.0).abs() < 1e-5 && back.green.abs() < 1e-5 && back.blue.abs() < 1e-5);
+    }
+}
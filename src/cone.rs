@@ -0,0 +1,105 @@
+use crate::vector3::Vector3;
+use crate::ray::Ray;
+use crate::scene::Intersectable;
+use crate::material::Material;
+use crate::material::TextureCoords;
+use crate::transform::Transform;
+use crate::frame::Frame;
+use crate::cylinder::{up, side_coords, cap_coords, side_tangents, cap_hit, quadratic_roots, solid_span};
+use serde::Deserialize;
+
+//A solid cone on a disk of radius around base, narrowing to a point height along axis. Textured like a cylinder
+//without a top: wrapped around the side and laid flat across the bottom.
+#[derive(Clone, Deserialize)]
+pub struct Cone {
+    pub base: Vector3,
+    #[serde(default = "up")]
+    pub axis: Vector3,
+    pub radius: f64,
+    pub height: f64,
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+}
+
+impl Cone {
+    pub fn surface_normal(&self, hit_point: &Vector3) -> Vector3 {
+        let frame = self.frame();
+        let p = frame.to_local(hit_point);
+        if self.on_bottom(&p) {
+            return -frame.y;
+        }
+        let slope = self.radius / self.height;
+        let radial = (p.x * p.x + p.z * p.z).sqrt().max(1e-12);
+        //the side leans back by the slope, so the normal tips up the axis by as much
+        frame.to_world_direction(&Vector3::new(p.x / radial, slope, p.z / radial)).normalize()
+    }
+
+    pub fn texture_coords(&self, hit_point: &Vector3) -> TextureCoords {
+        let p = self.frame().to_local(hit_point);
+        if self.on_bottom(&p) { cap_coords(&p, self.radius) } else { side_coords(&p, self.height) }
+    }
+
+    pub fn tangents(&self, hit_point: &Vector3) -> (Vector3, Vector3) {
+        let frame = self.frame();
+        let p = frame.to_local(hit_point);
+        if self.on_bottom(&p) {
+            return (frame.x, frame.z);
+        }
+        let (around, _) = side_tangents(&frame, &p);
+        let apex = frame.origin + frame.y * self.height;
+        (around, (*hit_point - apex).normalize()) //texture y runs from the tip down
+    }
+
+    fn frame(&self) -> Frame {
+        Frame::new(self.base, self.axis)
+    }
+
+    fn on_bottom(&self, p: &Vector3) -> bool {
+        let radius_here = self.radius * (1.0 - p.y / self.height);
+        p.y.abs() < ((p.x * p.x + p.z * p.z).sqrt() - radius_here).abs()
+    }
+}
+
+impl Intersectable for Cone {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let frame = self.frame();
+        let o = frame.to_local(&ray.origin);
+        let d = frame.to_local_direction(&ray.direction);
+        let k2 = (self.radius / self.height).powi(2);
+        let h = self.height - o.y; //the side is x^2 + z^2 = k^2 (height - y)^2
+        let mut nearest: Option<f64> = None;
+        let mut consider = |t: f64| {
+            if t >= 0.0 && nearest.is_none_or(|n| t < n) {
+                nearest = Some(t);
+            }
+        };
+        let a = d.x * d.x + d.z * d.z - k2 * d.y * d.y;
+        let b = 2.0 * (o.x * d.x + o.z * d.z + k2 * h * d.y);
+        let c = o.x * o.x + o.z * o.z - k2 * h * h;
+        if let Some((t0, t1)) = quadratic_roots(a, b, c) {
+            for &t in &[t0, t1] {
+                let y = o.y + d.y * t;
+                if y >= 0.0 && y <= self.height { //the equation also holds on the mirrored cone above the tip
+                    consider(t);
+                }
+            }
+        }
+        if let Some(t) = cap_hit(&o, &d, 0.0, self.radius) {
+            consider(t);
+        }
+        nearest
+    }
+
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(f64, f64)>> {
+        let frame = self.frame();
+        let o = frame.to_local(&ray.origin);
+        let d = frame.to_local_direction(&ray.direction);
+        let k2 = (self.radius / self.height).powi(2);
+        let h = self.height - o.y;
+        let a = d.x * d.x + d.z * d.z - k2 * d.y * d.y;
+        let b = 2.0 * (o.x * d.x + o.z * d.z + k2 * h * d.y);
+        let c = o.x * o.x + o.z * o.z - k2 * h * h;
+        Some(solid_span(a, b, c, o.y, d.y, self.height))
+    }
+}
@@ -0,0 +1,223 @@
+use crate::vector3::Vector3;
+use crate::ray::Ray;
+use crate::group;
+use crate::scene::{Element, Intersectable};
+use crate::material::{Material, TextureCoords};
+use crate::transform::Transform;
+use serde::Deserialize;
+use serde_json::Value;
+use std::convert::TryFrom;
+
+const PROBE: f64 = 1e-4;
+
+//Constructive solid geometry: two closed solids (spheres, boxes, cylinders, cones, tori or other CSG nodes) combined into one, e.g. a lens is the Intersection of two
+//overlapping spheres and a hollow ball the Difference of a sphere and a smaller one inside it. The whole solid is
+//shaded with the node's material, so the children may leave theirs out.
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "CsgDesc")]
+pub struct Csg {
+    pub operation: Operation,
+    pub left: Box<Element>,
+    pub right: Box<Element>,
+    pub material: Material,
+    pub transform: Option<Transform>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum Operation {
+    Union,
+    Intersection,
+    Difference, //left with right taken away
+}
+
+#[derive(Deserialize)]
+struct CsgDesc {
+    operation: Operation,
+    left: Value,
+    right: Value,
+    material: Material,
+    #[serde(default)]
+    transform: Option<Transform>,
+}
+
+impl TryFrom<CsgDesc> for Csg {
+    type Error = String;
+
+    fn try_from(desc: CsgDesc) -> Result<Self, Self::Error> {
+        let CsgDesc { operation, left, right, material, transform } = desc;
+        let child = |node: Value| -> Result<Element, String> {
+            match group::with_material(node, Some(&material))? {
+                //instances are only pointed at their geometry among the scene's top level elements
+                Element::Instance(_) => Err(String::from("an instance cannot be a CSG child")),
+                ref element if !element.is_solid() => Err(format!("a CSG child must be a closed solid, not a {}", element.obj_str())),
+                element => Ok(element),
+            }
+        };
+        Ok(Csg {
+            operation,
+            left: Box::new(child(left)?),
+            right: Box::new(child(right)?),
+            material,
+            transform,
+        })
+    }
+}
+
+impl Operation {
+    fn inside(&self, left: bool, right: bool) -> bool {
+        match *self {
+            Operation::Union => left || right,
+            Operation::Intersection => left && right,
+            Operation::Difference => left && !right,
+        }
+    }
+}
+
+impl Csg {
+    pub fn surface_normal(&self, hit_point: &Vector3) -> Vector3 {
+        let (child, flipped) = self.surface_at(hit_point);
+        let normal = child.surface_normal(hit_point);
+        if flipped { -normal } else { normal }
+    }
+
+    pub fn texture_coords(&self, hit_point: &Vector3) -> TextureCoords {
+        self.surface_at(hit_point).0.texture_coords(hit_point)
+    }
+
+    pub fn tangents(&self, hit_point: &Vector3) -> (Vector3, Vector3) {
+        self.surface_at(hit_point).0.tangents(hit_point)
+    }
+
+    //Which child's surface a point on the solid's surface lies on, and whether it is seen from inside that child
+    //(the walls of a hole cut by a difference). Each child is probed from just behind the point along its own
+    //normal; the one whose surface is hit right away is the one the point is on.
+    fn surface_at(&self, hit_point: &Vector3) -> (&Element, bool) {
+        let gap = |child: &Element| {
+            let normal = child.surface_normal(hit_point);
+            let probe = Ray {
+                origin: *hit_point - normal * PROBE,
+                direction: normal,
+                differential: None,
+                time: 0.0, //only asked of a solid caught at one moment, whose children have stopped moving
+            };
+            child.intersect(&probe).map_or(f64::INFINITY, |d| (d - PROBE).abs())
+        };
+        if gap(&self.left) <= gap(&self.right) {
+            (&self.left, false)
+        } else {
+            (&self.right, self.operation == Operation::Difference)
+        }
+    }
+}
+
+//The stretches of a line inside either, both or the first but not the second of two solids, from the stretches inside
+//each. Sweeps the ends of both sets in order, tracking whether the line is inside each solid; stretches that end
+//where the next begins are joined so there is no surface between them.
+fn combine(operation: Operation, left: &[(f64, f64)], right: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    let mut events: Vec<(f64, usize, bool)> = Vec::with_capacity(2 * (left.len() + right.len()));
+    for (child, intervals) in [left, right].iter().enumerate() {
+        for &(entry, exit) in intervals.iter() {
+            events.push((entry, child, true));
+            events.push((exit, child, false));
+        }
+    }
+    events.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    let mut inside = [false, false];
+    let mut combined: Vec<(f64, f64)> = Vec::new();
+    let mut entered = None;
+    for (t, child, entering) in events {
+        inside[child] = entering;
+        match (entered, operation.inside(inside[0], inside[1])) {
+            (None, true) => entered = Some(t),
+            (Some(entry), false) => {
+                entered = None;
+                match combined.last_mut() {
+                    Some(last) if last.1 >= entry => last.1 = t,
+                    _ if t > entry => combined.push((entry, t)),
+                    _ => {},
+                }
+            },
+            _ => {},
+        }
+    }
+    combined
+}
+
+impl Intersectable for Csg {
+    //The nearest end of a stretch inside the solid that is in front of the ray, whether the ray goes in or out there
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        self.intervals(ray)?
+            .into_iter()
+            .flat_map(|(entry, exit)| vec![entry, exit])
+            .find(|&t| t >= 0.0)
+    }
+
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(f64, f64)>> {
+        let left = self.left.intervals(ray)?;
+        let right = self.right.intervals(ray)?;
+        Some(combine(self.operation, &left, &right))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Intervals = Vec<(f64, f64)>;
+
+    fn hollow_ball() -> Csg {
+        serde_json::from_str(r#"{ "operation": "Difference",
+            "left": { "Sphere": { "center": { "x": 0, "y": 0, "z": 0 }, "radius": 2 } },
+            "right": { "Sphere": { "center": { "x": 0, "y": 0, "z": 0 }, "radius": 1 } },
+            "material": { "coloration": { "Color": { "red": 1, "green": 1, "blue": 1 } }, "albedo": 0.5, "surface": "Diffuse" } }"#).unwrap()
+    }
+
+    fn ray(origin: Vector3, direction: Vector3) -> Ray {
+        Ray { origin, direction, differential: None, time: 0.0 }
+    }
+
+    #[test]
+    fn combines_intervals() {
+        use Operation::*;
+        let cases: Vec<(Operation, Intervals, Intervals, Intervals)> = vec![
+            (Union, vec![(0.0, 1.0)], vec![(2.0, 3.0)], vec![(0.0, 1.0), (2.0, 3.0)]),
+            (Union, vec![(0.0, 2.0)], vec![(1.0, 3.0)], vec![(0.0, 3.0)]),
+            (Union, vec![(0.0, 1.0)], vec![(1.0, 2.0)], vec![(0.0, 2.0)]), //touching, so no surface between them
+            (Union, vec![(0.0, 4.0)], vec![(1.0, 2.0)], vec![(0.0, 4.0)]),
+            (Union, vec![], vec![(1.0, 2.0)], vec![(1.0, 2.0)]),
+            (Intersection, vec![(0.0, 2.0)], vec![(1.0, 3.0)], vec![(1.0, 2.0)]),
+            (Intersection, vec![(0.0, 1.0)], vec![(2.0, 3.0)], vec![]),
+            (Intersection, vec![(0.0, 1.0)], vec![(1.0, 2.0)], vec![]),
+            (Intersection, vec![(0.0, 4.0)], vec![(1.0, 2.0)], vec![(1.0, 2.0)]),
+            (Intersection, vec![(0.0, 2.0), (3.0, 5.0)], vec![(1.0, 4.0)], vec![(1.0, 2.0), (3.0, 4.0)]),
+            (Difference, vec![(0.0, 2.0)], vec![(1.0, 3.0)], vec![(0.0, 1.0)]),
+            (Difference, vec![(0.0, 4.0)], vec![(1.0, 2.0)], vec![(0.0, 1.0), (2.0, 4.0)]),
+            (Difference, vec![(0.0, 1.0)], vec![(1.0, 2.0)], vec![(0.0, 1.0)]),
+            (Difference, vec![(1.0, 2.0)], vec![(0.0, 4.0)], vec![]),
+            (Difference, vec![(-3.0, 3.0)], vec![], vec![(-3.0, 3.0)]), //a line starting inside
+        ];
+        for (operation, left, right, expected) in cases {
+            assert_eq!(combine(operation, &left, &right), expected, "{:?} of {:?} and {:?}", operation, left, right);
+        }
+    }
+
+    #[test]
+    fn ray_starting_inside_hits_the_way_out() {
+        let ball = hollow_ball();
+        let t = ball.intersect(&ray(Vector3::new(0.0, 0.0, 1.5), Vector3::new(0.0, 0.0, -1.0))).unwrap();
+        assert!((t - 0.5).abs() < 1e-9); //out through the inner wall
+        let t = ball.intersect(&ray(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0))).unwrap();
+        assert!((t - 1.0).abs() < 1e-9); //from the hollow, in through the inner wall
+    }
+
+    #[test]
+    fn inner_wall_of_a_hollow_ball_faces_the_hollow() {
+        let ball = hollow_ball();
+        let t = ball.intersect(&ray(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0))).unwrap();
+        assert!((t - 3.0).abs() < 1e-9);
+        let outer = ball.surface_normal(&Vector3::new(0.0, 0.0, 2.0));
+        assert!((outer - Vector3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+        let inner = ball.surface_normal(&Vector3::new(0.0, 0.0, 1.0));
+        assert!((inner - Vector3::new(0.0, 0.0, -1.0)).length() < 1e-9);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::vector3::Vector3;
+use crate::ray::Ray;
+use crate::scene::Intersectable;
+use crate::material::Material;
+use crate::material::TextureCoords;
+use crate::transform::Transform;
+use serde::Deserialize;
+
+//A box spanning min to max, optionally turned about its center by rotation (degrees about x, then y, then z).
+//Each face gets the whole texture, upright on the four sides and with x running along the x axis on the top and bottom.
+#[derive(Clone, Deserialize)]
+pub struct Cuboid {
+    pub min: Vector3,
+    pub max: Vector3,
+    #[serde(default = "Vector3::zero")]
+    pub rotation: Vector3,
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+}
+
+impl Cuboid {
+    pub fn surface_normal(&self, hit_point: &Vector3) -> Vector3 {
+        let (axis, sign) = self.face(hit_point);
+        self.axes()[axis] * sign
+    }
+
+    pub fn texture_coords(&self, hit_point: &Vector3) -> TextureCoords {
+        let (axis, sign) = self.face(hit_point);
+        let local = self.to_local(hit_point);
+        let half = self.half_size();
+        let (u_axis, v_axis) = face_axes(axis);
+        let u = local[u_axis] / half[u_axis] * if axis == 2 { sign } else if axis == 0 { -sign } else { 1.0 };
+        TextureCoords {
+            x: ((u + 1.0) * 0.5) as f32,
+            y: ((1.0 - local[v_axis] / half[v_axis]) * 0.5) as f32,
+        }
+    }
+
+    pub fn tangents(&self, hit_point: &Vector3) -> (Vector3, Vector3) {
+        let (axis, sign) = self.face(hit_point);
+        let axes = self.axes();
+        let (u_axis, v_axis) = face_axes(axis);
+        let u_sign = if axis == 2 { sign } else if axis == 0 { -sign } else { 1.0 };
+        (axes[u_axis] * u_sign, -axes[v_axis])
+    }
+
+    fn center(&self) -> Vector3 {
+        (self.min + self.max) * 0.5
+    }
+
+    fn half_size(&self) -> [f64; 3] {
+        let half = (self.max - self.min) * 0.5;
+        [half.x.abs(), half.y.abs(), half.z.abs()]
+    }
+
+    fn axes(&self) -> [Vector3; 3] {
+        rotation_axes(&self.rotation)
+    }
+
+    fn to_local(&self, point: &Vector3) -> [f64; 3] {
+        let offset = *point - self.center();
+        let axes = self.axes();
+        [offset.dot(&axes[0]), offset.dot(&axes[1]), offset.dot(&axes[2])]
+    }
+
+    //Which face a point on the surface is on: the axis it is furthest out along, relative to the box's size
+    fn face(&self, hit_point: &Vector3) -> (usize, f64) {
+        let local = self.to_local(hit_point);
+        let half = self.half_size();
+        let mut axis = 0;
+        for i in 1..3 {
+            if (local[i] / half[i]).abs() > (local[axis] / half[axis]).abs() {
+                axis = i;
+            }
+        }
+        (axis, local[axis].signum())
+    }
+}
+
+//The local axes that run across the texture's x and y on a face whose normal is along the given axis
+fn face_axes(axis: usize) -> (usize, usize) {
+    match axis {
+        0 => (2, 1),
+        1 => (0, 2),
+        _ => (0, 1),
+    }
+}
+
+//Where the x, y and z axes end up after turning by rotation degrees about x, then y, then z
+pub fn rotation_axes(rotation: &Vector3) -> [Vector3; 3] {
+    let (sx, cx) = rotation.x.to_radians().sin_cos();
+    let (sy, cy) = rotation.y.to_radians().sin_cos();
+    let (sz, cz) = rotation.z.to_radians().sin_cos();
+    let rotate = |v: Vector3| {
+        let v = Vector3::new(v.x, v.y * cx - v.z * sx, v.y * sx + v.z * cx);
+        let v = Vector3::new(v.x * cy + v.z * sy, v.y, -v.x * sy + v.z * cy);
+        Vector3::new(v.x * cz - v.y * sz, v.x * sz + v.y * cz, v.z)
+    };
+    [rotate(Vector3::new(1.0, 0.0, 0.0)), rotate(Vector3::new(0.0, 1.0, 0.0)), rotate(Vector3::new(0.0, 0.0, 1.0))]
+}
+
+impl Cuboid {
+    //Slab test in the box's own frame: where the ray's line enters and leaves the box, if it passes through it
+    fn span(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let origin = self.to_local(&ray.origin);
+        let axes = self.axes();
+        let direction = [ray.direction.dot(&axes[0]), ray.direction.dot(&axes[1]), ray.direction.dot(&axes[2])];
+        let half = self.half_size();
+        let mut t_near = f64::NEG_INFINITY;
+        let mut t_far = f64::INFINITY;
+        for i in 0..3 {
+            if direction[i].abs() < 1e-12 {
+                if origin[i].abs() > half[i] {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (-half[i] - origin[i]) / direction[i];
+            let t1 = (half[i] - origin[i]) / direction[i];
+            t_near = t_near.max(t0.min(t1));
+            t_far = t_far.min(t0.max(t1));
+        }
+        if t_near > t_far { None } else { Some((t_near, t_far)) }
+    }
+}
+
+impl Intersectable for Cuboid {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        match self.span(ray) {
+            Some((_, t_far)) if t_far < 0.0 => None,
+            Some((t_near, _)) if t_near >= 0.0 => Some(t_near),
+            Some((_, t_far)) => Some(t_far), //starting inside
+            None => None,
+        }
+    }
+
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(f64, f64)>> {
+        Some(self.span(ray).into_iter().collect())
+    }
+}
@@ -0,0 +1,194 @@
+use crate::vector3::Vector3;
+use crate::ray::Ray;
+use crate::scene::Intersectable;
+use crate::material::Material;
+use crate::material::TextureCoords;
+use crate::transform::Transform;
+use crate::frame::Frame;
+use serde::Deserialize;
+
+//A solid cylinder standing on the disk of radius around base and reaching height along axis, closed at both ends.
+//The texture wraps once around the side (x around, y from top to bottom) and is laid flat across each cap.
+#[derive(Clone, Deserialize)]
+pub struct Cylinder {
+    pub base: Vector3,
+    #[serde(default = "up")]
+    pub axis: Vector3,
+    pub radius: f64,
+    pub height: f64,
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+}
+
+pub fn up() -> Vector3 {
+    Vector3::new(0.0, 1.0, 0.0)
+}
+
+impl Cylinder {
+    pub fn surface_normal(&self, hit_point: &Vector3) -> Vector3 {
+        let frame = self.frame();
+        let p = frame.to_local(hit_point);
+        match self.part(&p) {
+            Part::Bottom => -frame.y,
+            Part::Top => frame.y,
+            Part::Side => frame.to_world_direction(&Vector3::new(p.x, 0.0, p.z)).normalize(),
+        }
+    }
+
+    pub fn texture_coords(&self, hit_point: &Vector3) -> TextureCoords {
+        let p = self.frame().to_local(hit_point);
+        match self.part(&p) {
+            Part::Side => side_coords(&p, self.height),
+            _ => cap_coords(&p, self.radius),
+        }
+    }
+
+    pub fn tangents(&self, hit_point: &Vector3) -> (Vector3, Vector3) {
+        let frame = self.frame();
+        let p = frame.to_local(hit_point);
+        match self.part(&p) {
+            Part::Side => side_tangents(&frame, &p),
+            _ => (frame.x, frame.z),
+        }
+    }
+
+    fn frame(&self) -> Frame {
+        Frame::new(self.base, self.axis)
+    }
+
+    //The caps are told apart from the side by whichever the point is closer to
+    fn part(&self, p: &Vector3) -> Part {
+        let side_gap = ((p.x * p.x + p.z * p.z).sqrt() - self.radius).abs();
+        if p.y.abs() < side_gap {
+            Part::Bottom
+        } else if (p.y - self.height).abs() < side_gap {
+            Part::Top
+        } else {
+            Part::Side
+        }
+    }
+}
+
+pub enum Part {
+    Bottom,
+    Top,
+    Side,
+}
+
+//Around the axis for x, from the top down for y
+pub fn side_coords(p: &Vector3, height: f64) -> TextureCoords {
+    TextureCoords {
+        x: ((p.z.atan2(p.x) / std::f64::consts::PI + 1.0) * 0.5) as f32,
+        y: (1.0 - p.y / height) as f32,
+    }
+}
+
+pub fn cap_coords(p: &Vector3, radius: f64) -> TextureCoords {
+    TextureCoords {
+        x: ((p.x / radius + 1.0) * 0.5) as f32,
+        y: ((p.z / radius + 1.0) * 0.5) as f32,
+    }
+}
+
+pub fn side_tangents(frame: &Frame, p: &Vector3) -> (Vector3, Vector3) {
+    let angle = p.z.atan2(p.x);
+    let around = frame.to_world_direction(&Vector3::new(-angle.sin(), 0.0, angle.cos()));
+    (around, -frame.y)
+}
+
+//Where a ray in the shape's local frame crosses the plane y = height within radius of the axis
+pub fn cap_hit(origin: &Vector3, direction: &Vector3, height: f64, radius: f64) -> Option<f64> {
+    if direction.y.abs() < 1e-12 {
+        return None;
+    }
+    let t = (height - origin.y) / direction.y;
+    let x = origin.x + direction.x * t;
+    let z = origin.z + direction.z * t;
+    if t >= 0.0 && x * x + z * z <= radius * radius { Some(t) } else { None }
+}
+
+//Both real roots of a t^2 + b t + c, smallest first
+pub fn quadratic_roots(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
+    if a.abs() < 1e-12 {
+        return None;
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    let (t0, t1) = ((-b - root) / (2.0 * a), (-b + root) / (2.0 * a));
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+//Where along a ray a t^2 + b t + c <= 0 and 0 <= o_y + d_y t <= height, as one stretch: the inside of a convex
+//solid of revolution whose side is the quadratic, cut off by its caps
+pub fn solid_span(a: f64, b: f64, c: f64, o_y: f64, d_y: f64, height: f64) -> Vec<(f64, f64)> {
+    let (infinity, neg_infinity) = (f64::INFINITY, f64::NEG_INFINITY);
+    let side = if a.abs() < 1e-12 { //along the side, which only bounds the ray on one end, if at all
+        if b.abs() < 1e-12 {
+            if c <= 0.0 { vec![(neg_infinity, infinity)] } else { Vec::new() }
+        } else if b > 0.0 {
+            vec![(neg_infinity, -c / b)]
+        } else {
+            vec![(-c / b, infinity)]
+        }
+    } else {
+        match quadratic_roots(a, b, c) {
+            Some((t0, t1)) if a > 0.0 => vec![(t0, t1)],
+            Some((t0, t1)) => vec![(neg_infinity, t0), (t1, infinity)], //both nappes of a double cone
+            None if a > 0.0 => Vec::new(),
+            None => vec![(neg_infinity, infinity)],
+        }
+    };
+    let caps = if d_y.abs() < 1e-12 {
+        if o_y >= 0.0 && o_y <= height { (neg_infinity, infinity) } else { return Vec::new() }
+    } else {
+        let (t0, t1) = (-o_y / d_y, (height - o_y) / d_y);
+        (t0.min(t1), t0.max(t1))
+    };
+    side.into_iter()
+        .map(|(entry, exit)| (entry.max(caps.0), exit.min(caps.1)))
+        .filter(|&(entry, exit)| entry <= exit)
+        .fold(None, |span: Option<(f64, f64)>, (entry, exit)| Some(span.map_or((entry, exit), |(e, x)| (e.min(entry), x.max(exit)))))
+        .into_iter()
+        .collect()
+}
+
+impl Intersectable for Cylinder {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let frame = self.frame();
+        let o = frame.to_local(&ray.origin);
+        let d = frame.to_local_direction(&ray.direction);
+        let mut nearest: Option<f64> = None;
+        let mut consider = |t: f64| {
+            if t >= 0.0 && nearest.is_none_or(|n| t < n) {
+                nearest = Some(t);
+            }
+        };
+        if let Some((t0, t1)) = quadratic_roots(d.x * d.x + d.z * d.z, 2.0 * (o.x * d.x + o.z * d.z), o.x * o.x + o.z * o.z - self.radius * self.radius) {
+            for &t in &[t0, t1] {
+                let y = o.y + d.y * t;
+                if y >= 0.0 && y <= self.height {
+                    consider(t);
+                }
+            }
+        }
+        if let Some(t) = cap_hit(&o, &d, 0.0, self.radius) {
+            consider(t);
+        }
+        if let Some(t) = cap_hit(&o, &d, self.height, self.radius) {
+            consider(t);
+        }
+        nearest
+    }
+
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(f64, f64)>> {
+        let frame = self.frame();
+        let o = frame.to_local(&ray.origin);
+        let d = frame.to_local_direction(&ray.direction);
+        let (a, b, c) = (d.x * d.x + d.z * d.z, 2.0 * (o.x * d.x + o.z * d.z), o.x * o.x + o.z * o.z - self.radius * self.radius);
+        Some(solid_span(a, b, c, o.y, d.y, self.height))
+    }
+}
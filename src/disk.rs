@@ -0,0 +1,51 @@
+use crate::vector3::Vector3;
+use crate::ray::Ray;
+use crate::scene::Intersectable;
+use crate::material::Material;
+use crate::material::TextureCoords;
+use crate::transform::Transform;
+use crate::frame::Frame;
+use crate::cylinder::{up, cap_coords, cap_hit};
+use serde::Deserialize;
+
+//A flat round disk facing along normal. Like a quad it is seen from both sides, and the texture is laid across it.
+#[derive(Clone, Deserialize)]
+pub struct Disk {
+    pub center: Vector3,
+    #[serde(default = "up")]
+    pub normal: Vector3,
+    pub radius: f64,
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+}
+
+impl Disk {
+    pub fn surface_normal(&self, _: &Vector3) -> Vector3 {
+        self.normal.normalize()
+    }
+
+    pub fn texture_coords(&self, hit_point: &Vector3) -> TextureCoords {
+        cap_coords(&self.frame().to_local(hit_point), self.radius)
+    }
+
+    pub fn tangents(&self, _: &Vector3) -> (Vector3, Vector3) {
+        let frame = self.frame();
+        (frame.x, frame.z)
+    }
+
+    fn frame(&self) -> Frame {
+        Frame::new(self.center, self.normal)
+    }
+}
+
+impl Intersectable for Disk {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let frame = self.frame();
+        let direction = frame.to_local_direction(&ray.direction);
+        if direction.y.abs() < 1e-6 { //edge on
+            return None;
+        }
+        cap_hit(&frame.to_local(&ray.origin), &direction, 0.0, self.radius)
+    }
+}
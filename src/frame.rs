@@ -0,0 +1,33 @@
+use crate::vector3::Vector3;
+
+//An orthonormal frame standing on origin with its y along axis, for shapes that are simplest to intersect in a space
+//where they sit upright at the origin (cylinders, cones, disks, tori). x and z are some pair of directions across the axis.
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    pub origin: Vector3,
+    pub x: Vector3,
+    pub y: Vector3,
+    pub z: Vector3,
+}
+
+impl Frame {
+    pub fn new(origin: Vector3, axis: Vector3) -> Frame {
+        let y = axis.normalize();
+        let helper = if y.x.abs() < 0.9 { Vector3::new(1.0, 0.0, 0.0) } else { Vector3::new(0.0, 0.0, 1.0) };
+        let z = helper.cross(&y).normalize();
+        let x = y.cross(&z);
+        Frame { origin, x, y, z }
+    }
+
+    pub fn to_local(self, point: &Vector3) -> Vector3 {
+        self.to_local_direction(&(*point - self.origin))
+    }
+
+    pub fn to_local_direction(self, direction: &Vector3) -> Vector3 {
+        Vector3::new(direction.dot(&self.x), direction.dot(&self.y), direction.dot(&self.z))
+    }
+
+    pub fn to_world_direction(self, direction: &Vector3) -> Vector3 {
+        self.x * direction.x + self.y * direction.y + self.z * direction.z
+    }
+}
@@ -0,0 +1,162 @@
+use crate::material::Material;
+use crate::scene::Element;
+use crate::transform::Transform;
+use serde::{Deserialize, Deserializer};
+use serde::de::Error;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+//A group moves its children together and gives a material to those that do not have their own, e.g.
+//{ "Group": { "transform": { ... }, "material": { ... }, "children": [ { "Sphere": { ... } }, { "Group": { ... } } ] } }.
+//Groups only exist in the scene description: they are flattened while it is read, each child ending up as an
+//ordinary element carrying the transforms of all the groups around it, so tracing never has to descend into them.
+#[derive(Deserialize)]
+struct Group {
+    children: Vec<Value>,
+    #[serde(default)]
+    material: Option<Material>,
+    #[serde(default)]
+    transform: Option<Transform>,
+}
+
+//For Scene::elements: the elements and groups of the scene as one flat list
+pub fn elements<'de, D>(deserializer: D) -> Result<Vec<Element>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let nodes = Vec::<Value>::deserialize(deserializer)?;
+    let mut elements = Vec::new();
+    for node in nodes {
+        flatten(node, None, None, &mut elements).map_err(D::Error::custom)?;
+    }
+    Ok(elements)
+}
+
+//For Scene::geometry: each named piece of geometry, which may be a whole group, as the elements it is made of
+pub fn geometry<'de, D>(deserializer: D) -> Result<HashMap<String, Vec<Arc<Element>>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let named = HashMap::<String, Value>::deserialize(deserializer)?;
+    let mut geometry = HashMap::new();
+    for (name, node) in named {
+        let mut elements = Vec::new();
+        flatten(node, None, None, &mut elements).map_err(D::Error::custom)?;
+        geometry.insert(name, elements.into_iter().map(Arc::new).collect());
+    }
+    Ok(geometry)
+}
+
+fn flatten(node: Value, parent: Option<&Transform>, material: Option<&Material>, out: &mut Vec<Element>) -> Result<(), String> {
+    if let Some(group) = node.get("Group") {
+        let group: Group = serde_json::from_value(group.clone()).map_err(|e| e.to_string())?;
+        let transform = compose(parent, group.transform.as_ref());
+        let material = group.material.as_ref().or(material);
+        for child in group.children {
+            flatten(child, transform.as_ref(), material, out)?;
+        }
+        return Ok(());
+    }
+    let mut element = with_material(node, material)?;
+    let transform = compose(parent, element.transform());
+    element.set_transform(transform);
+    out.push(element);
+    Ok(())
+}
+
+//Reads an element, giving it the material if it has none of its own (instances keep falling back to their target's).
+//The material has already been decoded once for everything sharing it, so the element is read with a cheap stand in
+//that is then swapped for a copy of it, rather than decoding the material's textures again for every element
+pub fn with_material(mut node: Value, material: Option<&Material>) -> Result<Element, String> {
+    let mut missing = false;
+    if let (Some(_), Some(object)) = (material, node.as_object_mut()) {
+        for (kind, fields) in object.iter_mut() {
+            match fields.as_object_mut() {
+                Some(fields) if kind != "Instance" && !fields.contains_key("material") => {
+                    fields.insert(String::from("material"), stand_in());
+                    missing = true;
+                },
+                _ => {},
+            }
+        }
+    }
+    let mut element: Element = serde_json::from_value(node).map_err(|e| e.to_string())?;
+    if let (true, Some(material)) = (missing, material) {
+        element.set_material(material.clone());
+    }
+    Ok(element)
+}
+
+fn stand_in() -> Value {
+    json!({ "coloration": { "Color": { "red": 0.0, "green": 0.0, "blue": 0.0 } }, "albedo": 0.0, "surface": "Diffuse" })
+}
+
+fn compose(outer: Option<&Transform>, inner: Option<&Transform>) -> Option<Transform> {
+    match (outer, inner) {
+        (Some(outer), Some(inner)) => Some(outer.compose(inner)),
+        (Some(t), None) | (None, Some(t)) => Some(t.clone()),
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Coloration;
+    use crate::vector3::Vector3;
+
+    #[derive(Deserialize)]
+    struct Scene {
+        #[serde(deserialize_with = "elements")]
+        elements: Vec<Element>,
+    }
+
+    fn read(json: &str) -> Vec<Element> {
+        serde_json::from_str::<Scene>(json).unwrap().elements
+    }
+
+    fn red(element: &Element) -> f32 {
+        match element.material().coloration {
+            Coloration::Color(c) => c.red,
+            _ => panic!("not a plain color"),
+        }
+    }
+
+    const TWO_LEVELS: &str = r#"{ "elements": [ { "Group": {
+        "transform": { "translate": { "x": 10, "y": 0, "z": 0 } },
+        "material": { "coloration": { "Color": { "red": 0.25, "green": 0, "blue": 0 } }, "albedo": 0.5, "surface": "Diffuse" },
+        "children": [ { "Group": {
+            "transform": { "scale": 2 },
+            "children": [
+                { "Sphere": { "center": { "x": 0, "y": 0, "z": 0 }, "radius": 1, "transform": { "translate": { "x": 0, "y": 1, "z": 0 } } } },
+                { "Sphere": { "center": { "x": 0, "y": 0, "z": 0 }, "radius": 1,
+                    "material": { "coloration": { "Color": { "red": 0.75, "green": 0, "blue": 0 } }, "albedo": 0.5, "surface": "Diffuse" } } }
+            ]
+        } } ]
+    } } ] }"#;
+
+    #[test]
+    fn nested_transforms_apply_inner_first() {
+        let elements = read(TWO_LEVELS);
+        assert_eq!(elements.len(), 2);
+        let placed = elements[0].transform().unwrap().point_to_world(&Vector3::zero());
+        assert!((placed - Vector3::new(10.0, 2.0, 0.0)).length() < 1e-9, "{:?}", placed); //moved up, scaled, then moved across
+        let placed = elements[1].transform().unwrap().point_to_world(&Vector3::new(1.0, 0.0, 0.0));
+        assert!((placed - Vector3::new(12.0, 0.0, 0.0)).length() < 1e-9, "{:?}", placed);
+    }
+
+    #[test]
+    fn group_material_only_fills_in() {
+        let elements = read(TWO_LEVELS);
+        assert_eq!(red(&elements[0]), 0.25); //inherited through the inner group
+        assert_eq!(red(&elements[1]), 0.75); //kept its own
+    }
+
+    #[test]
+    fn child_without_a_material_needs_a_group_to_give_it_one() {
+        let result = serde_json::from_str::<Scene>(r#"{ "elements": [ { "Group": { "children": [
+            { "Sphere": { "center": { "x": 0, "y": 0, "z": 0 }, "radius": 1 } } ] } } ] }"#);
+        assert!(result.is_err());
+    }
+}
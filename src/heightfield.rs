@@ -0,0 +1,314 @@
+use crate::vector3::Vector3;
+use crate::ray::Ray;
+use crate::scene::Intersectable;
+use crate::material::Material;
+use crate::material::TextureCoords;
+use crate::texture::Texture;
+use crate::transform::Transform;
+use crate::mesh::intersect_triangle;
+use image::{DynamicImage, GenericImageView};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+//Terrain from a grayscale elevation image, loaded like any texture e.g.
+//{ "Heightfield": { "image": { "asset": "dem" }, "origin": { ... }, "width": 100, "depth": 100, "height": 12, "material": ... }.
+//The image is stretched over width (along x) by depth (along z) from origin, with black at origin.y and white height above it.
+//Each texel is a vertex and each square of four is two triangles; only the top is a surface, there are no walls round the sides.
+#[derive(Deserialize)]
+struct HeightfieldDesc {
+    image: Texture,
+    origin: Vector3,
+    width: f64,
+    depth: f64,
+    height: f64,
+    material: Material,
+    #[serde(default)]
+    transform: Option<Transform>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "HeightfieldDesc")]
+pub struct Heightfield {
+    pub origin: Vector3,
+    pub width: f64,
+    pub depth: f64,
+    pub material: Material,
+    pub transform: Option<Transform>,
+    columns: usize,
+    rows: usize,
+    heights: Arc<Vec<f64>>, //above origin.y, a row of columns at a time
+    normals: Arc<Vec<Vector3>>, //per vertex, so lighting is smooth across the triangles
+    cell_bounds: Arc<Vec<(f64, f64)>>, //lowest and highest corner of each cell, to skip cells the ray passes over
+    lowest: f64,
+    highest: f64,
+}
+
+impl TryFrom<HeightfieldDesc> for Heightfield {
+    type Error = String;
+
+    fn try_from(desc: HeightfieldDesc) -> Result<Self, Self::Error> {
+        let (columns, rows) = (desc.image.image.width() as usize, desc.image.image.height() as usize);
+        if columns < 2 || rows < 2 {
+            return Err(String::from("a heightfield image needs at least 2x2 pixels"));
+        }
+        if desc.width <= 0.0 || desc.depth <= 0.0 {
+            return Err(String::from("a heightfield needs a positive width and depth"));
+        }
+        let heights: Vec<f64> = match *desc.image.image {
+            DynamicImage::ImageLuma16(ref luma) => luma.pixels().map(|p| p[0] as f64 / 65535.0 * desc.height).collect(), //keep the precision of 16 bit elevation data
+            ref image => image.to_luma().pixels().map(|p| p[0] as f64 / 255.0 * desc.height).collect(),
+        };
+
+        let (dx, dz) = (desc.width / (columns - 1) as f64, desc.depth / (rows - 1) as f64);
+        let at = |i: usize, j: usize| heights[j * columns + i];
+        let mut normals = Vec::with_capacity(columns * rows);
+        for j in 0..rows {
+            for i in 0..columns {
+                let (left, right) = (i.saturating_sub(1), (i + 1).min(columns - 1));
+                let (near, far) = (j.saturating_sub(1), (j + 1).min(rows - 1));
+                let slope_x = (at(right, j) - at(left, j)) / ((right - left) as f64 * dx);
+                let slope_z = (at(i, far) - at(i, near)) / ((far - near) as f64 * dz);
+                normals.push(Vector3::new(-slope_x, 1.0, -slope_z).normalize());
+            }
+        }
+        let mut cell_bounds = Vec::with_capacity((columns - 1) * (rows - 1));
+        for j in 0..rows - 1 {
+            for i in 0..columns - 1 {
+                let corners = [at(i, j), at(i + 1, j), at(i, j + 1), at(i + 1, j + 1)];
+                let low = corners.iter().cloned().fold(f64::INFINITY, f64::min);
+                let high = corners.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+                cell_bounds.push((low, high));
+            }
+        }
+        let lowest = heights.iter().cloned().fold(f64::INFINITY, f64::min);
+        let highest = heights.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        Ok(Heightfield {
+            origin: desc.origin,
+            width: desc.width,
+            depth: desc.depth,
+            material: desc.material,
+            transform: desc.transform,
+            columns,
+            rows,
+            heights: Arc::new(heights),
+            normals: Arc::new(normals),
+            cell_bounds: Arc::new(cell_bounds),
+            lowest,
+            highest,
+        })
+    }
+}
+
+impl Heightfield {
+    pub fn surface_normal(&self, hit_point: &Vector3) -> Vector3 {
+        let (i, j, fx, fz) = self.cell_at(hit_point);
+        let n = |i: usize, j: usize| self.normals[j * self.columns + i];
+        let near = n(i, j) * (1.0 - fx) + n(i + 1, j) * fx;
+        let far = n(i, j + 1) * (1.0 - fx) + n(i + 1, j + 1) * fx;
+        (near * (1.0 - fz) + far * fz).normalize()
+    }
+
+    pub fn texture_coords(&self, hit_point: &Vector3) -> TextureCoords { //laid over the terrain like the elevation image itself
+        let local = *hit_point - self.origin;
+        TextureCoords {
+            x: (local.x / self.width) as f32,
+            y: (local.z / self.depth) as f32,
+        }
+    }
+
+    pub fn tangents(&self, hit_point: &Vector3) -> (Vector3, Vector3) { //x and z tipped up the slope
+        let normal = self.surface_normal(hit_point);
+        let dpdu = Vector3::new(normal.y, -normal.x, 0.0).normalize();
+        let dpdv = Vector3::new(0.0, -normal.z, normal.y).normalize();
+        (dpdu, dpdv)
+    }
+
+    fn cell_size(&self) -> (f64, f64) {
+        (self.width / (self.columns - 1) as f64, self.depth / (self.rows - 1) as f64)
+    }
+
+    //The cell under a point and how far across it the point is, from 0 to 1 in x and z
+    fn cell_at(&self, point: &Vector3) -> (usize, usize, f64, f64) {
+        let (dx, dz) = self.cell_size();
+        let local = *point - self.origin;
+        let x = (local.x / dx).max(0.0).min((self.columns - 1) as f64);
+        let z = (local.z / dz).max(0.0).min((self.rows - 1) as f64);
+        let (i, j) = ((x as usize).min(self.columns - 2), (z as usize).min(self.rows - 2));
+        (i, j, x - i as f64, z - j as f64)
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Vector3 {
+        let (dx, dz) = self.cell_size();
+        Vector3::new(i as f64 * dx, self.heights[j * self.columns + i], j as f64 * dz)
+    }
+
+    //Nearest hit on the two triangles of a cell, for a ray already moved so the heightfield starts at the origin
+    fn intersect_cell(&self, origin: &Vector3, direction: &Vector3, i: usize, j: usize) -> Option<f64> {
+        let (a, b) = (self.vertex(i, j), self.vertex(i + 1, j));
+        let (c, d) = (self.vertex(i, j + 1), self.vertex(i + 1, j + 1));
+        let first = intersect_triangle(origin, direction, &a, &b, &d);
+        let second = intersect_triangle(origin, direction, &a, &d, &c);
+        match (first, second) {
+            (Some(t1), Some(t2)) => Some(t1.min(t2)),
+            (t1, t2) => t1.or(t2),
+        }
+    }
+}
+
+impl Intersectable for Heightfield {
+    //Clips the ray to the terrain's bounding box, then walks the cells it passes over in order (a 2D DDA), testing
+    //triangles only in cells whose heights overlap the ray's, so the first hit found is the nearest
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let origin = ray.origin - self.origin;
+        let direction = ray.direction;
+        let low = [0.0, self.lowest, 0.0];
+        let high = [self.width, self.highest, self.depth];
+        let o = [origin.x, origin.y, origin.z];
+        let d = [direction.x, direction.y, direction.z];
+        let (mut t_near, mut t_far) = (0.0f64, f64::INFINITY);
+        for axis in 0..3 {
+            if d[axis].abs() < 1e-12 {
+                if o[axis] < low[axis] || o[axis] > high[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (low[axis] - o[axis]) / d[axis];
+            let t1 = (high[axis] - o[axis]) / d[axis];
+            t_near = t_near.max(t0.min(t1));
+            t_far = t_far.min(t0.max(t1));
+        }
+        if t_near > t_far {
+            return None;
+        }
+
+        let (dx, dz) = self.cell_size();
+        let (i, j, _, _) = self.cell_at(&(ray.origin + direction * t_near));
+        let (mut i, mut j) = (i as i64, j as i64);
+        let step = |d: f64| if d > 0.0 { 1 } else { -1 };
+        let (step_i, step_j) = (step(direction.x), step(direction.z));
+        //ray distance to the next cell boundary in x and in z, and between boundaries
+        let boundary = |cell: i64, step: i64, size: f64, o: f64, d: f64| {
+            if d.abs() < 1e-12 {
+                f64::INFINITY
+            } else {
+                ((cell + if step > 0 { 1 } else { 0 }) as f64 * size - o) / d
+            }
+        };
+        let mut next_x = boundary(i, step_i, dx, origin.x, direction.x);
+        let mut next_z = boundary(j, step_j, dz, origin.z, direction.z);
+        let delta_x = if direction.x.abs() < 1e-12 { f64::INFINITY } else { dx / direction.x.abs() };
+        let delta_z = if direction.z.abs() < 1e-12 { f64::INFINITY } else { dz / direction.z.abs() };
+
+        let mut t_enter = t_near;
+        while i >= 0 && j >= 0 && i < self.columns as i64 - 1 && j < self.rows as i64 - 1 && t_enter <= t_far {
+            let t_exit = next_x.min(next_z).min(t_far);
+            let (low, high) = self.cell_bounds[j as usize * (self.columns - 1) + i as usize];
+            let (y_enter, y_exit) = (origin.y + direction.y * t_enter, origin.y + direction.y * t_exit);
+            if y_enter.min(y_exit) <= high && y_enter.max(y_exit) >= low {
+                if let Some(t) = self.intersect_cell(&origin, &direction, i as usize, j as usize) {
+                    return Some(t);
+                }
+            }
+            t_enter = t_exit;
+            if next_x < next_z {
+                next_x += delta_x;
+                i += step_i;
+            } else {
+                next_z += delta_z;
+                j += step_j;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::ColorSpace;
+    use image::{GrayImage, Luma};
+
+    //7 x 5 texels over 6 x 4 units with a ridge 3 high down the middle column and gentle bumps elsewhere
+    fn terrain() -> Heightfield {
+        let image = GrayImage::from_fn(7, 5, |x, y| if x == 3 { Luma([255]) } else { Luma([((x * 7 + y * 13) % 5 * 20) as u8]) });
+        let material = serde_json::from_str(r#"{ "coloration": { "Color": { "red": 1, "green": 1, "blue": 1 } }, "albedo": 0.5, "surface": "Diffuse" }"#).unwrap();
+        Heightfield::try_from(HeightfieldDesc {
+            image: Texture::new(DynamicImage::ImageLuma8(image), ColorSpace::Linear),
+            origin: Vector3::new(-3.0, -1.0, -2.0),
+            width: 6.0,
+            depth: 4.0,
+            height: 3.0,
+            material,
+            transform: None,
+        }).unwrap()
+    }
+
+    //The nearest hit over every triangle of every cell, to check the walk against
+    fn brute_force(field: &Heightfield, ray: &Ray) -> Option<f64> {
+        let origin = ray.origin - field.origin;
+        (0..field.rows - 1)
+            .flat_map(|j| (0..field.columns - 1).map(move |i| (i, j)))
+            .filter_map(|(i, j)| field.intersect_cell(&origin, &ray.direction, i, j))
+            .fold(None, |nearest: Option<f64>, t| Some(nearest.map_or(t, |n| n.min(t))))
+    }
+
+    fn check(field: &Heightfield, origin: Vector3, direction: Vector3) -> Option<f64> {
+        let ray = Ray { origin, direction: direction.normalize(), differential: None, time: 0.0 };
+        let (walked, expected) = (field.intersect(&ray), brute_force(field, &ray));
+        match (walked, expected) {
+            (Some(a), Some(b)) => assert!((a - b).abs() < 1e-9, "{:?} then {:?}: {} != {}", origin, direction, a, b),
+            _ => assert_eq!(walked, expected, "{:?} then {:?}", origin, direction),
+        }
+        walked
+    }
+
+    #[test]
+    fn enters_from_the_side_and_from_above() {
+        let field = terrain();
+        assert!(check(&field, Vector3::new(-6.0, 0.5, 0.3), Vector3::new(1.0, 0.0, 0.1)).is_some()); //into the ridge
+        assert!(check(&field, Vector3::new(0.2, 10.0, 0.1), Vector3::new(0.0, -1.0, 0.0)).is_some()); //straight down
+        assert!(check(&field, Vector3::new(-2.0, 6.0, -5.0), Vector3::new(0.3, -0.8, 0.6)).is_some());
+    }
+
+    #[test]
+    fn axis_aligned_rays() {
+        let field = terrain();
+        assert!(check(&field, Vector3::new(0.5, 2.5, -6.0), Vector3::new(0.0, -0.4, 1.0)).is_some()); //direction.x == 0
+        assert!(check(&field, Vector3::new(0.5, 0.0, -6.0), Vector3::new(0.0, 0.0, 1.0)).is_none()); //under the top, which has no walls
+        assert!(check(&field, Vector3::new(-1.0, 0.8, -6.0), Vector3::new(0.0, -0.2, 1.0)).is_some());
+        check(&field, Vector3::new(-5.0, -0.9, 1.0), Vector3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn starts_inside_the_bounds() {
+        let field = terrain();
+        assert!(check(&field, Vector3::new(-2.0, 1.0, 0.0), Vector3::new(1.0, -0.1, 0.2)).is_some());
+        check(&field, Vector3::new(1.5, 1.5, 1.0), Vector3::new(-1.0, 0.3, -0.5));
+    }
+
+    #[test]
+    fn passes_over_the_ridge() {
+        let field = terrain();
+        assert!(check(&field, Vector3::new(-6.0, 2.05, 0.0), Vector3::new(1.0, 0.0, 0.0)).is_none()); //just above the top
+        assert!(check(&field, Vector3::new(-6.0, 1.9, 0.0), Vector3::new(1.0, 0.0, 0.0)).is_some()); //just below it
+    }
+
+    #[test]
+    fn agrees_with_every_cell() {
+        let field = terrain();
+        let mut seed = 12345u32;
+        let mut next = || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            seed as f64 / 4_294_967_296.0
+        };
+        for _ in 0..2000 {
+            let origin = Vector3::new(next() * 12.0 - 6.0, next() * 6.0 - 1.5, next() * 10.0 - 5.0);
+            let direction = Vector3::new(next() * 2.0 - 1.0, next() * 2.0 - 1.0, next() * 2.0 - 1.0);
+            if direction.length() > 1e-3 {
+                check(&field, origin, direction);
+            }
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use crate::scene::Element;
+use crate::material::Material;
+use crate::transform::Transform;
+use serde::Deserialize;
+use std::sync::Arc;
+
+//Another placement of an element from the scene's geometry library, e.g. { "geometry": "chair", "transform": { ... } }.
+//Every instance shares the one copy of the geometry; material, if given, replaces the geometry's own.
+#[derive(Clone, Deserialize)]
+pub struct Instance {
+    pub geometry: String,
+    #[serde(default)]
+    pub material: Option<Material>,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+    #[serde(skip)]
+    pub target: Option<Arc<Element>>, //filled in by Scene::resolve_instances once the whole scene is read
+}
+
+impl Instance {
+    pub fn target(&self) -> &Element {
+        self.target.as_ref().expect("instance used before its geometry was resolved")
+    }
+}
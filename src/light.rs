@@ -1,7 +1,6 @@
 use crate::vector3::Vector3;
 use crate::color::Color;
 // use crate::point::Point;
-use crate::scene::Intersection;
 use serde::{Serialize, Deserialize};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -36,17 +35,6 @@ impl Light {
         }
     }
 
-    pub fn in_light(&self, shadow_intersection: Option<Intersection>, distance: f64) -> bool {
-        match *self {
-            Light::Directional(ref d) => {
-                shadow_intersection.is_none()
-            },
-            Light::Spherical(ref s) => {
-                shadow_intersection.is_none() || shadow_intersection.unwrap().distance > distance
-            },
-        }
-    }
-
     pub fn intensity(&self, hit_point: &Vector3) -> f32 {
         match *self {
             Light::Directional(ref d) => {
@@ -8,6 +8,23 @@ mod sphere;
 use sphere::Sphere;
 mod plane;
 use plane::Plane;
+mod cuboid;
+mod quad;
+mod frame;
+mod cylinder;
+mod cone;
+mod disk;
+mod torus;
+mod transform;
+mod instance;
+mod group;
+mod csg;
+mod sdf;
+mod heightfield;
+mod mesh;
+mod bezier;
+mod subdivision;
+mod camera;
 mod scene;
 use scene::Scene;
 use crate::scene::Intersectable;
@@ -18,6 +35,16 @@ use crate::light::DirectionalLight;
 use crate::light::SphericalLight;
 mod material;
 use material::Material;
+mod texture;
+use texture::{Texture, Footprint};
+mod output;
+use output::OutputQuery;
+mod asset;
+use asset::AssetStore;
+mod procedural;
+mod spectrum;
+mod medium;
+use color::ColorSpace;
 use image::*;
 
 use tokio::sync::Mutex;
@@ -25,10 +52,6 @@ use warp::{Filter, http::StatusCode, filters::BoxedFilter, http::Response, Reply
 use std::sync::Arc;
 use serde::{Serialize, Deserialize};
 use std::env;
-use std::fs::File;
-use std::io::Read;
-use std::path::{Path, PathBuf};
-use std::io;
 use std::fs;
 
 const BLACK: Color = Color {
@@ -37,73 +60,78 @@ const BLACK: Color = Color {
     blue: 0.0,
 };
 
-struct Png {
-    inner: io::Result<File>,
-}
+const BACKGROUND: Rgba<u8> = Rgba([108, 119, 149, 255]); //slate grey, seen where prime rays hit nothing
 
-impl Png {
-    pub fn new(path: &Path) -> Self {
-         Png {inner : File::open(path)}
-    }
+struct EncodedImage {
+    data: Vec<u8>,
+    content_type: &'static str,
 }
 
-impl Reply for Png {
+impl Reply for EncodedImage {
     #[inline]
     fn into_response(self) -> warp::reply::Response {
-        match self.inner {
-            Ok(mut file) => {
-                let mut data : Vec<u8> = Vec::new();
-                    match file.read_to_end(&mut data) {
-                        Err(why) => {
-                            println!("Error: {:?}", why);
-                            return Response::new(String::new().into());
-                        }
-                        Ok(_) => {
-                            let mut res = Response::new(data.into());
-                            res.headers_mut()
-                                .insert(CONTENT_TYPE, http::HeaderValue::from_static("image/png"));
-                            return res;
-                        },
-                    }
-            }
-            Err(why) => {
-                println!("Error: {:?}", why);
-                return Response::new(String::new().into());
-            }
-        }
+        let mut res = Response::new(self.data.into());
+        res.headers_mut()
+            .insert(CONTENT_TYPE, http::HeaderValue::from_static(self.content_type));
+        res
     }
-} 
+}
 
-async fn tracer_route_handler(b: bytes::Bytes) -> Result<impl warp::Reply, warp::Rejection> {
+async fn tracer_route_handler(query: OutputQuery, accept: Option<String>, b: bytes::Bytes, assets: Arc<AssetStore>) -> Result<warp::reply::Response, warp::Rejection> {
+    let format = match output::negotiate(accept.as_deref(), &query) {
+        Ok(f) => f,
+        Err(why) => {
+            println!("Error: {}", why);
+            return Ok(warp::reply::with_status(why, StatusCode::NOT_ACCEPTABLE).into_response());
+        }
+    };
     let s: &str = std::str::from_utf8(&b).unwrap();
-    let scene: Scene = serde_json::from_str(s).unwrap();
+    let mut scene: Scene = match asset::with_store(&assets, || serde_json::from_str(s)) { //textures may name uploaded assets
+        Ok(scene) => scene,
+        Err(why) => {
+            println!("error parsing scene: {}", why);
+            return Ok(warp::reply::with_status(why.to_string(), StatusCode::BAD_REQUEST).into_response());
+        }
+    };
+
+    if let Err(why) = scene.resolve_instances() {
+        println!("error parsing scene: {}", why);
+        return Ok(warp::reply::with_status(why, StatusCode::BAD_REQUEST).into_response());
+    }
 
     let img: DynamicImage = render(&scene);
     assert_eq!(scene.width, img.width());
     assert_eq!(scene.height, img.height());
-    // let cargo_path = env!("CARGO_MANIFEST_DIR");
-    // let path = format!("{}/test.png", cargo_path);
-    println!("Getting current_dir");
-    let path = env::current_dir().unwrap().join("test.png");
-    println!("Got current_dir. Saving image to: {}", path.display());
-    let version = img.save(&path);
-    println!("Save image line completed");
-    match version {
-        Ok(_v) => {
-            // println!("Alright! {:?}", v);
-            println!("Alright! Saved image to: {}", path.display());
+    println!("Encoding image as: {:?}", format);
+    match output::encode(&img, format) {
+        Ok(data) => {
+            println!("Alright! Sending back {} bytes of {}", data.len(), format.content_type());
+            Ok(EncodedImage { data, content_type: format.content_type() }.into_response())
         },
         Err(e) => {
-            println!("error parsing: {:?}", e);
+            println!("error encoding: {:?}", e);
+            Ok(warp::reply::with_status(e.to_string(), StatusCode::INTERNAL_SERVER_ERROR).into_response())
+        }
+    }
+}
+
+async fn asset_route_handler(name: String, b: bytes::Bytes, assets: Arc<AssetStore>) -> Result<warp::reply::Response, warp::Rejection> {
+    match assets.insert(&name, &b) {
+        Ok(()) => {
+            println!("Stored asset: {} ({} bytes)", name, b.len());
+            Ok(warp::reply::with_status(String::new(), StatusCode::CREATED).into_response())
+        },
+        Err(why) => {
+            println!("Error: {}", why);
+            Ok(warp::reply::with_status(why, StatusCode::BAD_REQUEST).into_response())
         }
     }
-    println!("Sending back image at: {}", path.display());
-    Ok(Png::new(Path::new(&path)))
-    // let path = format!("C:/Users/samue/Documents/rust-tracer/test.png");
 }
 
 #[tokio::main]
 async fn main() {
+    let assets = Arc::new(AssetStore::new());
+    let with_assets = warp::any().map(move || assets.clone());
     let body_to_string = warp::body::content_length_limit(1024 * 32)
         .and(warp::body::bytes())
         .map(|bytes: bytes::Bytes| {
@@ -113,9 +141,20 @@ async fn main() {
     let tracer_route = warp::post()
         .and(warp::path("trace"))
         .and(warp::path::end())
+        .and(warp::query::<OutputQuery>())
+        .and(warp::header::optional::<String>("accept"))
         .and(body_to_string)
+        .and(with_assets.clone())
         .and_then(tracer_route_handler);
-    let routes = (tracer_route);
+    let asset_route = warp::put()
+        .and(warp::path("assets"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::body::content_length_limit(1024 * 1024 * 16))
+        .and(warp::body::bytes())
+        .and(with_assets)
+        .and_then(asset_route_handler);
+    let routes = tracer_route.or(asset_route);
 
     let port = env::var("PORT")
         .unwrap_or_else(|_| "3000".to_string())
@@ -133,26 +172,26 @@ async fn main() {
 }
 
 pub fn render(sceneInstance: &Scene) -> DynamicImage {
-    let mut image = DynamicImage::new_rgb8(sceneInstance.width, sceneInstance.height);
-    let slate_grey = Rgba::from_channels(108, 119, 149, 255);
-    let sky_blue = Rgba::from_channels(135, 206, 250, 255);
+    let mut image: ImageBuffer<Rgb<u16>, Vec<u16>> = ImageBuffer::new(sceneInstance.width, sceneInstance.height); //16 bits per channel so any output depth can be encoded from it
+    let background = Color::from_rgba(BACKGROUND); //encoded like everything else, so edges that partly miss blend into it
+    let sky_blue = Rgb([135 * 257, 206 * 257, 250 * 257]);
+    let wavelengths = spectrum::samples(sceneInstance.spectral_samples); //empty unless rendering spectrally
+    let samples = sceneInstance.samples.max(1);
     for x in 0..sceneInstance.width {
         for y in 0..sceneInstance.height {
-            let ray = Ray::create_prime(x, y, sceneInstance);
-
-            let intersection = sceneInstance.trace(&ray);
-            match intersection {
-                Some(ele) => {
-                    let mut color = Color {
-                        red: 0.0,
-                        blue: 0.0,
-                        green: 0.0,
-                    };
-                    color = get_color(&sceneInstance, &ray, &ele, 0);
-                    image.put_pixel(x, y, color.clamp().to_rgba());
-                },
-                None    => image.put_pixel(x, y, slate_grey),
+            let mut color = BLACK;
+            let mut missed = 0;
+            for sample in 0..samples {
+                let time = sceneInstance.camera.shutter.time(x, y, sample, samples);
+                let lens = sceneInstance.camera.lens_point(x, y, sample);
+                let ray = Ray::create_prime(x, y, time, lens, sceneInstance);
+                match sample_color(sceneInstance, &ray, &wavelengths) {
+                    Some(c) => color = color + c,
+                    None => missed += 1,
+                }
             }
+            color = (color + background * missed as f32) * (1.0 / samples as f32);
+            image.put_pixel(x, y, color.to_rgb16_in(sceneInstance.color_space));
 
             // if scene.sphere.intersect(&ray) {
             //     let c = &scene.sphere.color.to_rgba();
@@ -162,45 +201,124 @@ pub fn render(sceneInstance: &Scene) -> DynamicImage {
             // }
         }
     }
-    image
+    DynamicImage::ImageRgb16(image)
 }
 
-fn get_color(scene: &Scene, ray: &Ray, intersection: &Intersection, depth: u32) -> Color {
+//The color seen along a prime ray, or None when it hits nothing and there is no fog for it to see
+fn sample_color(scene: &Scene, ray: &Ray, wavelengths: &[(f32, Color)]) -> Option<Color> {
+    let intersection = scene.trace(ray);
+    if intersection.is_none() && scene.fog.is_none() {
+        return None;
+    }
+    let seen = |wavelength: Option<f32>| {
+        let color = match intersection {
+            Some(ref ele) => get_color(scene, ray, ele, 0, wavelength),
+            None => spectrum::evaluate(Color::from_rgba(BACKGROUND), wavelength), //seen through the fog
+        };
+        fogged(scene, ray, intersection.as_ref().map(|i| i.distance), color, wavelength)
+    };
+    if wavelengths.is_empty() {
+        Some(seen(None))
+    } else {
+        Some(wavelengths.iter().fold(BLACK, |color, &(wavelength, weight)| color + seen(Some(wavelength)) * weight))
+    }
+}
+
+fn get_color(scene: &Scene, ray: &Ray, intersection: &Intersection, depth: u32, wavelength: Option<f32>) -> Color { //wavelength in nm, only set in spectral renders
     let hit_point = ray.origin + (ray.direction * intersection.distance);
-    let surface_normal = intersection.element.surface_normal(&hit_point);
+    let mut geometric_normal = intersection.element.surface_normal(&hit_point);
+    if intersection.element.is_two_sided() && geometric_normal.dot(&ray.direction) > 0.0 { //seen from behind
+        geometric_normal = -geometric_normal;
+    }
+    let surface_normal = intersection.element.shading_normal(&hit_point, &geometric_normal);
+
+    let footprint = ray.differential.as_ref().and_then(|d| intersection.element.footprint(&hit_point, &geometric_normal, d));
 
     let material = intersection.element.material();
     match material.surface {
-        material::SurfaceType::Diffuse => diffuse_color(scene, intersection, &hit_point, &surface_normal),
-        material::SurfaceType::Reflective { reflectivity } => {
-            let mut color = diffuse_color(scene, intersection, &hit_point, &surface_normal);
-            let reflection_ray = Ray::create_reflection(surface_normal, ray.direction, hit_point, scene.shadow_bias);
+        material::SurfaceType::Diffuse => diffuse_color(scene, intersection, &hit_point, &surface_normal, footprint.as_ref(), wavelength),
+        material::SurfaceType::Reflective { ref reflectivity } => {
+            let reflectivity = material.scalar(reflectivity, &intersection.element.texture_coords(&hit_point), &intersection.element.pattern_point(&hit_point));
+            let mut color = diffuse_color(scene, intersection, &hit_point, &surface_normal, footprint.as_ref(), wavelength);
+            let reflection_ray = Ray::create_reflection(surface_normal, ray.direction, hit_point, scene.shadow_bias, ray.time);
             color = color * (1.0 - reflectivity);
-            color = color + (cast_ray(scene, &reflection_ray, depth + 1) * reflectivity);
+            color = color + (cast_ray(scene, &reflection_ray, depth + 1, wavelength) * reflectivity);
             color
         }
-        material::SurfaceType::Refractive { index, transparency } => {
+        material::SurfaceType::Refractive { index, ref transparency, absorption, ref dispersion } => {
+            let index = match (dispersion, wavelength) {
+                (Some(dispersion), Some(wavelength)) => dispersion.index(wavelength),
+                _ => index,
+            };
             let mut refraction_color = BLACK;
             let kr = fresnel(ray.direction, surface_normal, index) as f32;
-            let surface_color = material.coloration.color(&intersection.element.texture_coords(&hit_point));
+            let texture_coords = intersection.element.texture_coords(&hit_point);
+            let pattern_point = intersection.element.pattern_point(&hit_point);
+            let surface_color = spectrum::evaluate(material.color(&texture_coords, &pattern_point, footprint.as_ref()), wavelength);
+            let transparency = material.scalar(transparency, &texture_coords, &pattern_point);
 
             //Calculating the refractive colors
             if kr < 1.0 { //Fresnel > 1 means that the surface appears to be reflective. Here it behaves as it should i.e. refractions
-                let transmission_ray = Ray::create_transmission(surface_normal, ray.direction, hit_point, scene.shadow_bias, index).unwrap();
-                refraction_color = cast_ray(scene, &transmission_ray, depth + 1);
+                let transmission_ray = Ray::create_transmission(surface_normal, ray.direction, hit_point, scene.shadow_bias, index, ray.time).unwrap();
+                refraction_color = cast_ray(scene, &transmission_ray, depth + 1, wavelength);
             }
 
             //Calculating the reflective colors
-            let reflection_ray =Ray::create_reflection(surface_normal, ray.direction, hit_point, scene.shadow_bias);
-            let reflection_color = cast_ray(scene, &reflection_ray, depth + 1);
+            let reflection_ray =Ray::create_reflection(surface_normal, ray.direction, hit_point, scene.shadow_bias, ray.time);
+            let reflection_color = cast_ray(scene, &reflection_ray, depth + 1, wavelength);
             let mut color = reflection_color * kr + refraction_color * (1.0 - kr);
             color = color * transparency * surface_color;
+            if let Some(absorption) = absorption {
+                if ray.direction.dot(&geometric_normal) > 0.0 { //hit from inside: the ray crossed the medium to get here
+                    color = color * spectrum::evaluate(absorption, wavelength).transmittance(intersection.distance as f32);
+                }
+            }
             color
         }
+        material::SurfaceType::Metal { ref ior } => {
+            let texture_coords = intersection.element.texture_coords(&hit_point);
+            let (n, k) = ior.ior();
+            let reflection_ray = Ray::create_reflection(surface_normal, ray.direction, hit_point, scene.shadow_bias, ray.time);
+            let cos_i = (-ray.direction.dot(&surface_normal)).clamp(0.0, 1.0) as f32;
+            let reflectance = Color {
+                red: fresnel_conductor(cos_i, n.red, k.red),
+                green: fresnel_conductor(cos_i, n.green, k.green),
+                blue: fresnel_conductor(cos_i, n.blue, k.blue),
+            };
+            let tint = material.color(&texture_coords, &intersection.element.pattern_point(&hit_point), footprint.as_ref());
+            cast_ray(scene, &reflection_ray, depth + 1, wavelength) * spectrum::evaluate(reflectance * tint, wavelength)
+        }
+        material::SurfaceType::Subsurface { mean_free_path, scattering_color } => {
+            diffuse_color(scene, intersection, &hit_point, &surface_normal, footprint.as_ref(), wavelength)
+                + subsurface_color(scene, intersection, &hit_point, &geometric_normal, mean_free_path, scattering_color, wavelength)
+        }
+        material::SurfaceType::Volume { ref medium } => {
+            let beyond = Ray {
+                origin: hit_point + ray.direction * scene.shadow_bias,
+                direction: ray.direction,
+                differential: None,
+                time: ray.time,
+            };
+            if ray.direction.dot(&geometric_normal) > 0.0 { //leaving: the ray has been inside the medium since its origin
+                let behind = cast_ray(scene, &beyond, depth + 1, wavelength);
+                return medium.integrate(scene, ray, intersection.distance, behind, wavelength);
+            }
+            if depth + 1 >= scene.max_recursion_depth {
+                return BLACK;
+            }
+            match scene.trace(&beyond) {
+                Some(ref next) if next.same_element(intersection) => get_color(scene, &beyond, next, depth + 1, wavelength), //straight through to the far side
+                Some(ref next) => { //something inside the volume
+                    let behind = get_color(scene, &beyond, next, depth + 1, wavelength);
+                    medium.integrate(scene, &beyond, next.distance, behind, wavelength)
+                },
+                None => BLACK,
+            }
+        }
     }
 }
 
-fn diffuse_color(sceneInstance: &Scene, ele: &Intersection, hit_point: &Vector3, surface_normal: &Vector3) -> Color { //gets the normal diffuse lighting effect
+fn diffuse_color(sceneInstance: &Scene, ele: &Intersection, hit_point: &Vector3, surface_normal: &Vector3, footprint: Option<&Footprint>, wavelength: Option<f32>) -> Color { //gets the normal diffuse lighting effect
     let mut color = Color {
         red: 0.0,
         blue: 0.0,
@@ -211,25 +329,24 @@ fn diffuse_color(sceneInstance: &Scene, ele: &Intersection, hit_point: &Vector3,
         let shadow_ray = Ray {
             origin: *hit_point + (direction_to_light * sceneInstance.shadow_bias),
             direction: direction_to_light,
+            differential: None,
+            time: ele.time,
         };
-        let shadow_intersection = sceneInstance.trace(&shadow_ray);
+        let transmittance = sceneInstance.light_transmittance(&shadow_ray, light.distance(hit_point), wavelength);
         // if (x > 250 && x < 290) && (y > 160) {
         //     match &shadow_intersection {
         //         Some(blocker) => {println!("At: {} Blocked by: {}", ele.element.obj_str(), blocker.element.obj_str())},
         //         None => {println!("No block")}
         //     }
         // }
-        let in_light = light.in_light(shadow_intersection, light.distance(hit_point)); 
-        let light_intensity = if in_light {
-             light.intensity(&hit_point)
-        } else { 0.0 };
+        let light_intensity = light.intensity(hit_point);
         let light_power = (surface_normal.dot(&direction_to_light) as f32).max(0.0) * light_intensity;
         // if (x > 250 && x < 290) && (y > 160) {
         //     println!("Power: {}, Intent: {}", light_power, light_intensity)
         // }
-        let light_reflected = ele.element.albedo() / std::f32::consts::PI;
-        let light_color = light.color() * light_power * light_reflected;
-        color = color + (ele.element.color(&hit_point) * light_color);
+        let light_reflected = ele.element.albedo(hit_point) / std::f32::consts::PI;
+        let light_color = spectrum::evaluate(light.color(), wavelength) * transmittance * light_power * light_reflected;
+        color = color + (spectrum::evaluate(ele.element.color(hit_point, footprint), wavelength) * light_color);
         // if (x > 250 && x < 290) && (y > 160) {
         //     color = Color {
         //         red: 0.0,
@@ -241,6 +358,51 @@ fn diffuse_color(sceneInstance: &Scene, ele: &Intersection, hit_point: &Vector3,
     color
 }
 
+//Light that enters the element on the way from each light and diffuses through it to the hit point. The thickness it
+//crosses is measured along the direction to the light and looked up in Burley's normalized diffusion profile
+//(the share of light that gets further than that), so thin or back lit parts glow and thick ones stay dark
+fn subsurface_color(scene: &Scene, ele: &Intersection, hit_point: &Vector3, normal: &Vector3, mean_free_path: Color, scattering_color: Color, wavelength: Option<f32>) -> Color {
+    let (mean_free_path, scattering_color) = (spectrum::evaluate(mean_free_path, wavelength), spectrum::evaluate(scattering_color, wavelength));
+    let mut color = BLACK;
+    for light in &scene.lights {
+        let direction_to_light = light.direction_to_light(hit_point);
+        if normal.dot(&direction_to_light) > 0.0 {
+            continue; //lit from the front, which diffuse_color already covers
+        }
+        let inward = Ray {
+            origin: *hit_point - (*normal * scene.shadow_bias),
+            direction: direction_to_light,
+            differential: None,
+            time: ele.time,
+        };
+        let thickness = match ele.element.intersect(&inward) {
+            Some(d) => d,
+            None => continue, //the light is not behind any part of the element
+        };
+        let entry_point = inward.origin + direction_to_light * thickness;
+        let entry_normal = ele.element.surface_normal(&entry_point);
+        let shadow_ray = Ray {
+            origin: entry_point + (direction_to_light * scene.shadow_bias),
+            direction: direction_to_light,
+            differential: None,
+            time: ele.time,
+        };
+        let transmittance = scene.light_transmittance(&shadow_ray, light.distance(&entry_point), wavelength);
+        let light_power = (entry_normal.dot(&direction_to_light) as f32).max(0.0) * light.intensity(&entry_point);
+        let profile = |mfp: f32| {
+            let r = thickness as f32 / mfp.max(1e-6);
+            0.25 * (-r).exp() + 0.75 * (-r / 3.0).exp()
+        };
+        let diffused = Color {
+            red: profile(mean_free_path.red),
+            green: profile(mean_free_path.green),
+            blue: profile(mean_free_path.blue),
+        };
+        color = color + spectrum::evaluate(light.color(), wavelength) * transmittance * diffused * scattering_color * (light_power / std::f32::consts::PI);
+    }
+    color
+}
+
 fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> f64 {
     let i_dot_n = incident.dot(&normal);
     let mut eta_i = 1.0;
@@ -263,14 +425,40 @@ fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> f64 {
     }
 }
 
-pub fn cast_ray(scene: &Scene, ray: &Ray, depth: u32) -> Color {
+//Unpolarized reflectance of a conductor with complex index n + ik, seen from air at an angle whose cosine is cos_i
+fn fresnel_conductor(cos_i: f32, n: f32, k: f32) -> f32 {
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let t0 = n * n - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * n * n * k * k).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * cos_i * a;
+    let r_s = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let r_p = r_s * (t3 - t4) / (t3 + t4);
+    (r_s + r_p) / 2.0
+}
+
+pub fn cast_ray(scene: &Scene, ray: &Ray, depth: u32, wavelength: Option<f32>) -> Color {
     if depth >= scene.max_recursion_depth {
         return BLACK;
     }
 
     let intersection = scene.trace(&ray);
-    intersection.map(|i| get_color(scene, &ray, &i, depth))
-        .unwrap_or(BLACK)
+    match intersection {
+        Some(i) => fogged(scene, ray, Some(i.distance), get_color(scene, ray, &i, depth, wavelength), wavelength),
+        None => fogged(scene, ray, None, BLACK, wavelength),
+    }
+}
+
+//The color seen along a ray through the scene's fog, if it has any. distance is None for rays that hit nothing
+fn fogged(scene: &Scene, ray: &Ray, distance: Option<f64>, color: Color, wavelength: Option<f32>) -> Color {
+    match scene.fog {
+        Some(ref fog) => fog.medium.integrate(scene, ray, distance.unwrap_or(fog.extent), color, wavelength),
+        None => color,
+    }
 }
 
 fn test_can_render_scene() {
@@ -286,6 +474,7 @@ fn test_can_render_scene() {
                     z: -3.5,
                 },
                 radius: 1.5,
+                end_center: None,
                 material: Material {
                     // coloration: material::Coloration::Texture( image::open(String::from("C:/Users/samue/Documents/rust-tracer/checkerboard.png")).unwrap()  ),
                     coloration: material::Coloration::Color(Color  {
@@ -293,9 +482,14 @@ fn test_can_render_scene() {
                         green: 1.0,
                         blue: 0.2,
                     }),
-                    albedo: 0.15,
-                    surface: material::SurfaceType::Reflective {reflectivity: 0.3}
-                }
+                    albedo: material::ScalarMap::Constant(0.15),
+                    uv_transform: material::UvTransform::default(),
+                    normal_map: None,
+                    bump_map: None,
+                    alpha_cutoff: 0.5,
+                    surface: material::SurfaceType::Reflective {reflectivity: material::ScalarMap::Constant(0.3)}
+                },
+                transform: None
             } ), 
             scene::Element::Sphere(Sphere {
                 center: Vector3 {
@@ -304,6 +498,7 @@ fn test_can_render_scene() {
                     z: -1.5, //-2.5
                 },
                 radius: 0.3,
+                end_center: None,
                 material: Material {
                     // coloration: material::Coloration::Texture( image::open(String::from("C:/Users/samue/Documents/rust-tracer/checkerboard-2.png")).unwrap()  ),
                     coloration: material::Coloration::Color(Color  {
@@ -311,9 +506,14 @@ fn test_can_render_scene() {
                         green: 1.0,
                         blue: 1.0,
                     }),
-                    albedo: 0.18,
-                    surface: material::SurfaceType::Refractive { index: 1.5, transparency: 0.7} //trans:1.0
-                } 
+                    albedo: material::ScalarMap::Constant(0.18),
+                    uv_transform: material::UvTransform::default(),
+                    normal_map: None,
+                    bump_map: None,
+                    alpha_cutoff: 0.5,
+                    surface: material::SurfaceType::Refractive { index: 1.5, transparency: material::ScalarMap::Constant(0.7), absorption: None, dispersion: None } //trans:1.0
+                },
+                transform: None 
             } ),
             scene::Element::Sphere(Sphere {
                 center: Vector3 {
@@ -322,16 +522,22 @@ fn test_can_render_scene() {
                     z: -2.5, //-2.5
                 },
                 radius: 1.0,
+                end_center: None,
                 material: Material {
-                    coloration: material::Coloration::Texture( image::open(String::from("C:/Users/samue/Documents/rust-tracer/checkerboard-2.png")).unwrap()  ),
+                    coloration: material::Coloration::Texture( Texture::new(image::open(String::from("C:/Users/samue/Documents/rust-tracer/checkerboard-2.png")).unwrap(), ColorSpace::Srgb) ),
                     // coloration: material::Coloration::Color(Color  {
                     //     red: 1.0,
                     //     green: 1.0,
                     //     blue: 1.0,
                     // }),
-                    albedo: 0.18,
+                    albedo: material::ScalarMap::Constant(0.18),
+                    uv_transform: material::UvTransform::default(),
+                    normal_map: None,
+                    bump_map: None,
+                    alpha_cutoff: 0.5,
                     surface: material::SurfaceType::Diffuse
-                } 
+                },
+                transform: None 
             } ),
             scene::Element::Plane(Plane {
                 p0: Vector3 {
@@ -345,10 +551,15 @@ fn test_can_render_scene() {
                     z: 0.0,
                 },
                 material: Material {
-                    coloration: material::Coloration::Texture( image::open(String::from("C:/Users/samue/Documents/rust-tracer/checkerboard.png")).unwrap()  ),
-                    albedo: 0.3,
+                    coloration: material::Coloration::Texture( Texture::new(image::open(String::from("C:/Users/samue/Documents/rust-tracer/checkerboard.png")).unwrap(), ColorSpace::Srgb) ),
+                    albedo: material::ScalarMap::Constant(0.3),
+                    uv_transform: material::UvTransform::default(),
+                    normal_map: None,
+                    bump_map: None,
+                    alpha_cutoff: 0.5,
                     surface: material::SurfaceType::Diffuse
-                }
+                },
+                transform: None
             } ),
             scene::Element::Plane(Plane { //the back wall
                 p0: Vector3 {
@@ -363,9 +574,14 @@ fn test_can_render_scene() {
                 },
                 material: Material {
                     coloration: material::Coloration::Color(Color::from_rgba(Rgba::from_channels(135, 206, 250, 255))),
-                    albedo: 0.3,
+                    albedo: material::ScalarMap::Constant(0.3),
+                    uv_transform: material::UvTransform::default(),
+                    normal_map: None,
+                    bump_map: None,
+                    alpha_cutoff: 0.5,
                     surface: material::SurfaceType::Diffuse//material::SurfaceType::Reflective {reflectivity: 0.3}
-                }
+                },
+                transform: None
             } ),
         ], 
         lights: vec ! [
@@ -411,6 +627,12 @@ fn test_can_render_scene() {
         ],
         shadow_bias: 0.000000001,
         max_recursion_depth: 5,
+        color_space: ColorSpace::Srgb,
+        spectral_samples: 0,
+        fog: None,
+        samples: 1,
+        camera: camera::Camera::default(),
+        geometry: std::collections::HashMap::new(),
     };
 
     let img: DynamicImage = render(&scene);
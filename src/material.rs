@@ -1,41 +1,300 @@
 use crate::color::Color;
-// use crate::texture::Texture;
-use image::{DynamicImage, GenericImageView, Pixel, Rgba, ImageBuffer};
-use serde::{Serialize, Deserialize};
+use crate::texture::{Texture, Footprint};
+use crate::procedural::{Checker, Noise, Turbulence, Marble, Wood, Gradient};
+use crate::vector3::Vector3;
+use crate::spectrum::Dispersion;
+use crate::medium::Medium;
+use serde::Deserialize;
 use serde::de::{self, Deserializer, Visitor, SeqAccess, MapAccess, IntoDeserializer};
 use std::fmt;
-extern crate base64;
 
 #[derive(Clone, Deserialize)]
 pub struct Material {
     pub coloration: Coloration,
-    pub albedo: f32,
+    pub albedo: ScalarMap,
     pub surface: SurfaceType,
+    #[serde(default)]
+    pub uv_transform: UvTransform,
+    pub normal_map: Option<NormalMap>,
+    pub bump_map: Option<BumpMap>,
+    #[serde(default = "default_alpha_cutoff")]
+    pub alpha_cutoff: f32, //texels of a textured coloration with alpha at or below this are holes that rays pass through
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+fn default_alpha_cutoff() -> f32 {
+    0.5
+}
+
+//A tangent space normal map in the OpenGL convention (red along +x of the texture, green towards the top of the image).
+//Normal maps hold directions rather than colors so they should be sent with "color_space": "Linear".
+#[derive(Clone, Deserialize)]
+pub struct NormalMap {
+    pub texture: Texture,
+    #[serde(default = "one")]
+    pub strength: f32,
+}
+
+//Any coloration read as a height field (its luminance); the slope of the heights tilts the normal
+#[derive(Clone, Deserialize)]
+pub struct BumpMap {
+    pub height: Coloration,
+    #[serde(default = "one")]
+    pub strength: f32,
+    #[serde(default = "default_bump_step")]
+    pub step: f32, //distance in texture coordinates over which the slope is measured
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+fn default_bump_step() -> f32 {
+    1e-3
+}
+
+impl Material {
+    pub fn color(&self, texture_coords: &TextureCoords, hit_point: &Vector3, footprint: Option<&Footprint>) -> Color {
+        let footprint = footprint.map(|f| self.uv_transform.apply_footprint(f));
+        self.coloration.sample(&self.uv_transform.apply(texture_coords), hit_point, footprint.as_ref())
+    }
+
+    pub fn has_cutout(&self) -> bool {
+        match &self.coloration {
+            Coloration::Texture(tex) => tex.has_alpha(),
+            _ => false,
+        }
+    }
+
+    pub fn is_opaque_at(&self, texture_coords: &TextureCoords) -> bool {
+        match &self.coloration {
+            Coloration::Texture(tex) => tex.alpha(&self.uv_transform.apply(texture_coords), None) > self.alpha_cutoff,
+            _ => true,
+        }
+    }
+
+    //samples one of the material's scalar maps through the same uv transform as the coloration
+    pub fn scalar(&self, map: &ScalarMap, texture_coords: &TextureCoords, hit_point: &Vector3) -> f32 {
+        map.value(&self.uv_transform.apply(texture_coords), hit_point)
+    }
+
+    //dpdu and dpdv are the directions on the surface in which the element's texture coords x and y grow;
+    //pattern_point takes a point near the hit to where world space procedural heights are looked up for it
+    pub fn shading_normal<F: Fn(&Vector3) -> Vector3>(&self, normal: &Vector3, dpdu: Vector3, dpdv: Vector3, texture_coords: &TextureCoords, hit_point: &Vector3, pattern_point: F) -> Vector3 {
+        let (tangent, bitangent) = self.uv_transform.tangents(dpdu, dpdv);
+        let coords = self.uv_transform.apply(texture_coords);
+        //re-orthogonalise against the normal so the frame stays sane where the parameterisation is skewed
+        let tangent = (tangent - *normal * normal.dot(&tangent)).normalize();
+        let bitangent = (bitangent - *normal * normal.dot(&bitangent) - tangent * tangent.dot(&bitangent)).normalize();
+
+        let mut shading_normal = *normal;
+        if let Some(map) = &self.normal_map {
+            let texel = map.texture.sample(&coords, None);
+            let x = (texel.red * 2.0 - 1.0) * map.strength;
+            let y = (texel.green * 2.0 - 1.0) * map.strength;
+            let z = texel.blue * 2.0 - 1.0;
+            //image rows grow downwards (texture y) while green points up the image
+            shading_normal = (tangent * x as f64 - bitangent * y as f64 + shading_normal * z.max(0.0) as f64).normalize();
+        }
+        if let Some(map) = &self.bump_map {
+            let height = |du: f32, dv: f32| {
+                let shifted = TextureCoords { x: coords.x + du, y: coords.y + dv };
+                let shifted_point = pattern_point(&(*hit_point + tangent * du as f64 + bitangent * dv as f64)); //for world space procedural heights
+                map.height.sample(&shifted, &shifted_point, None).luminance()
+            };
+            let h = height(0.0, 0.0);
+            let dhdu = (height(map.step, 0.0) - h) / map.step;
+            let dhdv = (height(0.0, map.step) - h) / map.step;
+            shading_normal = (shading_normal - (tangent * dhdu as f64 + bitangent * dhdv as f64) * map.strength as f64).normalize();
+        }
+        shading_normal
+    }
+}
+
+//Applied to the element's texture coordinates before sampling: scale first, then rotate (degrees, counter clockwise) and finally offset.
+//e.g. { "scale": [4.0, 4.0] } repeats a texture four times as often in both directions
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct UvTransform {
+    pub scale: [f32; 2],
+    pub offset: [f32; 2],
+    pub rotation: f32,
+}
+
+impl Default for UvTransform {
+    fn default() -> Self {
+        UvTransform {
+            scale: [1.0, 1.0],
+            offset: [0.0, 0.0],
+            rotation: 0.0,
+        }
+    }
+}
+
+impl UvTransform {
+    pub fn apply(&self, texture_coords: &TextureCoords) -> TextureCoords {
+        let (u, v) = self.linear(texture_coords.x, texture_coords.y);
+        TextureCoords {
+            x: u + self.offset[0],
+            y: v + self.offset[1],
+        }
+    }
+
+    pub fn apply_footprint(&self, footprint: &Footprint) -> Footprint { //the offset does not change how fast coordinates vary
+        let (dudx, dvdx) = self.linear(footprint.dudx, footprint.dvdx);
+        let (dudy, dvdy) = self.linear(footprint.dudy, footprint.dvdy);
+        Footprint { dudx, dvdx, dudy, dvdy }
+    }
+
+    //the surface directions in which the transformed coordinates grow, given those of the untransformed ones
+    pub fn tangents(&self, dpdu: Vector3, dpdv: Vector3) -> (Vector3, Vector3) {
+        let (sin, cos) = (self.rotation.to_radians().sin() as f64, self.rotation.to_radians().cos() as f64);
+        let (sx, sy) = (self.scale[0] as f64, self.scale[1] as f64);
+        //columns of the inverse of rotation * scale, up to the determinant which only changes lengths
+        let tangent = dpdu * (cos * sy) + dpdv * (-sin * sx);
+        let bitangent = dpdu * (sin * sy) + dpdv * (cos * sx);
+        (tangent, bitangent)
+    }
+
+    fn linear(&self, u: f32, v: f32) -> (f32, f32) {
+        let (u, v) = (u * self.scale[0], v * self.scale[1]);
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        (u * cos - v * sin, u * sin + v * cos)
+    }
+}
+
+#[derive(Clone, Deserialize)]
 pub enum SurfaceType {
     Diffuse,
-    Reflective { reflectivity: f32 },
-    Refractive { index: f32, transparency: f32 },
+    Reflective { reflectivity: ScalarMap },
+    //absorption is the fraction of each channel absorbed per unit of distance travelled inside (Beer-Lambert),
+    //so thick parts of an object take on more of the tint than thin ones. dispersion replaces index in spectral renders
+    Refractive {
+        index: f32,
+        transparency: ScalarMap,
+        #[serde(default)]
+        absorption: Option<Color>,
+        #[serde(default)]
+        dispersion: Option<Dispersion>,
+    },
+    Metal { ior: Conductor },
+    //an invisible boundary with a medium inside it; rays cross it and pick up the medium between entering and leaving
+    Volume { medium: Medium },
+    //a translucent solid: light entering anywhere on the element is carried through it and tinted by scattering_color,
+    //fading with the distance travelled inside over mean_free_path (per channel, so e.g. red can travel further than blue in skin)
+    Subsurface { mean_free_path: Color, scattering_color: Color },
+}
+
+//The complex index of refraction n + ik of a metal, per RGB channel. Either a preset e.g. "Gold",
+//or measured values e.g. { "n": { "red": 0.2, ... }, "k": { "red": 3.9, ... } }
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Conductor {
+    Preset(MetalPreset),
+    Ior { n: Color, k: Color },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum MetalPreset {
+    Gold,
+    Silver,
+    Copper,
+    Aluminium,
+    Iron,
+    Chromium,
+}
+
+impl Conductor {
+    pub fn ior(&self) -> (Color, Color) {
+        let rgb = |red, green, blue| Color { red, green, blue };
+        match *self {
+            Conductor::Ior { n, k } => (n, k),
+            //measured spectral data averaged at roughly 650, 550 and 450nm
+            Conductor::Preset(MetalPreset::Gold) => (rgb(0.143, 0.374, 1.442), rgb(3.983, 2.385, 1.603)),
+            Conductor::Preset(MetalPreset::Silver) => (rgb(0.155, 0.117, 0.138), rgb(4.828, 3.122, 2.147)),
+            Conductor::Preset(MetalPreset::Copper) => (rgb(0.200, 0.924, 1.102), rgb(3.912, 2.452, 2.142)),
+            Conductor::Preset(MetalPreset::Aluminium) => (rgb(1.657, 0.880, 0.521), rgb(9.224, 6.270, 4.837)),
+            Conductor::Preset(MetalPreset::Iron) => (rgb(2.912, 2.950, 2.585), rgb(3.089, 2.932, 2.767)),
+            Conductor::Preset(MetalPreset::Chromium) => (rgb(3.180, 3.180, 2.010), rgb(3.300, 3.330, 3.040)),
+        }
+    }
+}
+
+//A material parameter that is either the same everywhere e.g. 0.3, or read from the luminance of
+//a coloration at the hit e.g. { "Texture": { "asset": "roughness" } } or { "Noise": { ... } }
+#[derive(Clone)]
+pub enum ScalarMap {
+    Constant(f32),
+    Map(Coloration),
+}
+
+//Read by hand rather than untagged so that a mistake inside a map is reported as it is, not as "did not match any variant"
+impl<'de> Deserialize<'de> for ScalarMap {
+    fn deserialize<D>(deserializer: D) -> Result<ScalarMap, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ScalarMapVisitor;
+
+        impl<'de> Visitor<'de> for ScalarMapVisitor {
+            type Value = ScalarMap;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a number or a map of coloration type to its value")
+            }
+
+            fn visit_f64<E>(self, value: f64) -> Result<ScalarMap, E> where E: de::Error, {
+                Ok(ScalarMap::Constant(value as f32))
+            }
+
+            fn visit_i64<E>(self, value: i64) -> Result<ScalarMap, E> where E: de::Error, {
+                Ok(ScalarMap::Constant(value as f32))
+            }
+
+            fn visit_u64<E>(self, value: u64) -> Result<ScalarMap, E> where E: de::Error, {
+                Ok(ScalarMap::Constant(value as f32))
+            }
+
+            fn visit_map<V>(self, map: V) -> Result<ScalarMap, V::Error> where V: MapAccess<'de>, {
+                Coloration::deserialize(de::value::MapAccessDeserializer::new(map)).map(ScalarMap::Map)
+            }
+        }
+
+        deserializer.deserialize_any(ScalarMapVisitor)
+    }
+}
+
+impl ScalarMap {
+    pub fn value(&self, texture_coords: &TextureCoords, hit_point: &Vector3) -> f32 {
+        match self {
+            ScalarMap::Constant(v) => *v,
+            ScalarMap::Map(coloration) => coloration.sample(texture_coords, hit_point, None).luminance(),
+        }
+    }
 }
 
 #[derive(Clone)]
 pub enum Coloration {
     Color(Color),
-    Texture(DynamicImage)
+    Texture(Texture),
+    Checker(Checker),
+    Noise(Noise),
+    Turbulence(Turbulence),
+    Marble(Marble),
+    Wood(Wood),
+    Gradient(Gradient),
 }
 
 impl Coloration {
-    pub fn color(&self, texture_coords: &TextureCoords) -> Color {
+    pub fn sample(&self, texture_coords: &TextureCoords, hit_point: &Vector3, footprint: Option<&Footprint>) -> Color {
         match self {
             Coloration::Color(c) => *c,
-            Coloration::Texture(tex) => {
-                let tex_x = wrap(texture_coords.x, tex.width());
-                let tex_y = wrap(texture_coords.y, tex.height());
-            
-                Color::from_rgba(tex.get_pixel(tex_x, tex_y))
-            }
+            Coloration::Texture(tex) => tex.sample(texture_coords, footprint),
+            Coloration::Checker(c) => c.color(texture_coords, hit_point),
+            Coloration::Noise(n) => n.color(texture_coords, hit_point),
+            Coloration::Turbulence(t) => t.color(texture_coords, hit_point),
+            Coloration::Marble(m) => m.color(texture_coords, hit_point),
+            Coloration::Wood(w) => w.color(texture_coords, hit_point),
+            Coloration::Gradient(g) => g.color(texture_coords, hit_point),
         }
     }
 }
@@ -46,7 +305,7 @@ impl<'de> Deserialize<'de> for Coloration {
         D: Deserializer<'de>,
     {
 
-        enum Field { Color, Texture };
+        enum Field { Color, Texture, Checker, Noise, Turbulence, Marble, Wood, Gradient }
 
         // This part could also be generated independently by:
         //
@@ -74,6 +333,12 @@ impl<'de> Deserialize<'de> for Coloration {
                         match value {
                             "Color" => Ok(Field::Color),
                             "Texture" => Ok(Field::Texture),
+                            "Checker" => Ok(Field::Checker),
+                            "Noise" => Ok(Field::Noise),
+                            "Turbulence" => Ok(Field::Turbulence),
+                            "Marble" => Ok(Field::Marble),
+                            "Wood" => Ok(Field::Wood),
+                            "Gradient" => Ok(Field::Gradient),
                             _ => Err(de::Error::unknown_field(value, FIELDS)),
                         }
                     }
@@ -94,7 +359,8 @@ impl<'de> Deserialize<'de> for Coloration {
 
             fn visit_map<V>(self, mut map: V) -> Result<Coloration, V::Error> where V: MapAccess<'de>, {
                 let mut color = None;
-                let mut texture: std::option::Option<String> = None;
+                let mut texture: std::option::Option<Texture> = None;
+                let mut procedural: std::option::Option<Coloration> = None; //the image-free colorations
                 while let Some(key) = map.next_key()? {
                     match key {
                         Field::Color => {
@@ -109,25 +375,33 @@ impl<'de> Deserialize<'de> for Coloration {
                             }
                             texture = Some(map.next_value()?);
                         }
+                        _ => {
+                            if procedural.is_some() {
+                                return Err(de::Error::custom("only one procedural coloration can be given"));
+                            }
+                            procedural = Some(match key {
+                                Field::Checker => Coloration::Checker(map.next_value()?),
+                                Field::Noise => Coloration::Noise(map.next_value()?),
+                                Field::Turbulence => Coloration::Turbulence(map.next_value()?),
+                                Field::Marble => Coloration::Marble(map.next_value()?),
+                                Field::Wood => Coloration::Wood(map.next_value()?),
+                                _ => Coloration::Gradient(map.next_value()?),
+                            });
+                        }
                     }
                 }
                 match color {
                     Some(inner) => Ok(Coloration::Color(inner)),
                     None => {
                         match texture {
-                            Some(inner) => {
-                                let bytes = base64::decode(inner).unwrap();
-                                // println!("bytes[0]: {}", bytes[0]);
-                                let img: DynamicImage = image::load_from_memory(&bytes).unwrap();
-                                Ok(Coloration::Texture(img))
-                            },
-                            None => Err(de::Error::missing_field("color or text"))
+                            Some(inner) => Ok(Coloration::Texture(inner)),
+                            None => procedural.ok_or_else(|| de::Error::missing_field("color, texture or a procedural coloration"))
                         }
                     }
                 }
             }
         }
-        const FIELDS: &'static [&'static str] = &["Color", "Texture"];
+        const FIELDS: &[&str] = &["Color", "Texture", "Checker", "Noise", "Turbulence", "Marble", "Wood", "Gradient"];
         deserializer.deserialize_struct("Coloration", FIELDS, ColorationVisitor)
     }
 }
@@ -137,13 +411,46 @@ pub struct TextureCoords {
     pub y: f32,
 }
 
-fn wrap(val: f32, bound: u32) -> u32 { //to make sure that we do not go outside the sample texture file we are using as our texture i.e. DynamicImage
-    let signed_bound = bound as i32;
-    let float_coord = val * bound as f32;
-    let wrapped_coord = (float_coord as i32) % signed_bound;
-    if wrapped_coord < 0 {
-        (wrapped_coord + signed_bound) as u32
-    } else {
-        wrapped_coord as u32
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uv(scale: [f32; 2], offset: [f32; 2], rotation: f32, x: f32, y: f32) -> (f32, f32) {
+        let transformed = UvTransform { scale, offset, rotation }.apply(&TextureCoords { x, y });
+        (transformed.x, transformed.y)
     }
-}
\ No newline at end of file
+
+    fn assert_close(a: (f32, f32), b: (f32, f32)) {
+        assert!((a.0 - b.0).abs() < 1e-5 && (a.1 - b.1).abs() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn default_leaves_coordinates_alone() {
+        let transformed = UvTransform::default().apply(&TextureCoords { x: 0.25, y: 0.75 });
+        assert_close((transformed.x, transformed.y), (0.25, 0.75));
+    }
+
+    #[test]
+    fn scales_then_rotates_then_offsets() {
+        assert_close(uv([4.0, 2.0], [0.0, 0.0], 0.0, 0.5, 0.5), (2.0, 1.0));
+        assert_close(uv([1.0, 1.0], [0.0, 0.0], 90.0, 1.0, 0.0), (0.0, 1.0)); //counter clockwise
+        assert_close(uv([2.0, 1.0], [0.5, 0.25], 90.0, 1.0, 0.0), (0.5, 2.25));
+    }
+
+    #[test]
+    fn scalar_map_reads_numbers_and_colorations() {
+        let coords = TextureCoords { x: 0.0, y: 0.0 };
+        for &(json, expected) in &[("0.25", 0.25), ("1", 1.0), (r#"{ "Color": { "red": 1, "green": 1, "blue": 1 } }"#, 1.0)] {
+            let map: ScalarMap = serde_json::from_str(json).unwrap();
+            assert!((map.value(&coords, &Vector3::zero()) - expected).abs() < 1e-5, "{}", json);
+        }
+    }
+
+    #[test]
+    fn scalar_map_passes_on_what_is_wrong_with_a_map() {
+        let error = serde_json::from_str::<ScalarMap>(r#"{ "Color": { "red": 1, "green": 1 } }"#).err().unwrap().to_string();
+        assert!(error.contains("blue"), "{}", error);
+        let error = serde_json::from_str::<ScalarMap>(r#"{ "Colour": {} }"#).err().unwrap().to_string();
+        assert!(error.contains("Colour"), "{}", error);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::color::Color;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum;
+use serde::{Deserialize, Deserializer};
+use serde::de::Error;
+
+//A homogeneous participating medium. absorption and scattering are per unit of distance and per channel;
+//anisotropy is the Henyey-Greenstein g, from -1 (light bounces back towards where it came from) through 0
+//(scatters evenly in all directions) to 1 (keeps going forward, e.g. the glow around a light seen through haze)
+#[derive(Clone, Debug, Deserialize)]
+pub struct Medium {
+    pub absorption: Color,
+    pub scattering: Color,
+    #[serde(default, deserialize_with = "anisotropy")]
+    pub anisotropy: f32, //strictly between -1 and 1, where the phase function would become a spike
+    #[serde(default = "default_steps", deserialize_with = "steps")]
+    pub steps: u32, //samples taken along a ray when gathering light scattered towards it, at most MAX_STEPS
+}
+
+//Each step traces a shadow ray to every light, so a volume costs steps x lights x samples rays per pixel it covers
+const MAX_STEPS: u32 = 256;
+
+//Once this little of the light behind gets through, what lies further in cannot be seen either
+const OPAQUE: f32 = 1e-3;
+
+fn default_steps() -> u32 {
+    32
+}
+
+fn steps<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match u32::deserialize(deserializer)? {
+        steps if (1..=MAX_STEPS).contains(&steps) => Ok(steps),
+        steps => Err(D::Error::custom(format!("a medium takes from 1 to {} steps, not {}", MAX_STEPS, steps))),
+    }
+}
+
+fn anisotropy<'de, D>(deserializer: D) -> Result<f32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match f32::deserialize(deserializer)? {
+        g if g > -1.0 && g < 1.0 => Ok(g),
+        g => Err(D::Error::custom(format!("a medium's anisotropy lies strictly between -1 and 1, not {}", g))),
+    }
+}
+
+//Fog fills the whole scene up to extent away from the camera, beyond which rays that hit nothing stop picking it up
+#[derive(Clone, Debug, Deserialize)]
+pub struct Fog {
+    #[serde(flatten)]
+    pub medium: Medium,
+    #[serde(default = "default_extent")]
+    pub extent: f64,
+}
+
+fn default_extent() -> f64 {
+    100.0
+}
+
+impl Medium {
+    pub fn extinction(&self, wavelength: Option<f32>) -> Color {
+        spectrum::evaluate(self.absorption + self.scattering, wavelength)
+    }
+
+    pub fn transmittance(&self, distance: f64, wavelength: Option<f32>) -> Color {
+        self.extinction(wavelength).transmittance(distance as f32)
+    }
+
+    //What is seen along distance of ray through the medium when background is behind it: the background dimmed by
+    //the medium plus the light that the medium scatters towards the ray on the way (single scattering only).
+    //The steps stop where the medium has grown too thick to see further into
+    pub fn integrate(&self, scene: &Scene, ray: &Ray, distance: f64, background: Color, wavelength: Option<f32>) -> Color {
+        let scattering = spectrum::evaluate(self.scattering, wavelength);
+        let steps = self.steps.max(1);
+        let step = distance / steps as f64;
+        let mut in_scattered = Color { red: 0.0, green: 0.0, blue: 0.0 };
+        for i in 0..steps {
+            let t = (i as f64 + 0.5) * step;
+            let seen = self.transmittance(t, wavelength);
+            if seen.red.max(seen.green).max(seen.blue) < OPAQUE {
+                break;
+            }
+            let point = ray.origin + ray.direction * t;
+            let mut light_here = Color { red: 0.0, green: 0.0, blue: 0.0 };
+            for light in &scene.lights {
+                let direction_to_light = light.direction_to_light(&point);
+                let shadow_ray = Ray {
+                    origin: point,
+                    direction: direction_to_light,
+                    differential: None,
+                    time: ray.time,
+                };
+                let transmittance = scene.light_transmittance(&shadow_ray, light.distance(&point), wavelength);
+                let phase = henyey_greenstein(ray.direction.dot(&direction_to_light) as f32, self.anisotropy);
+                light_here = light_here + spectrum::evaluate(light.color(), wavelength) * transmittance * (light.intensity(&point) * phase);
+            }
+            in_scattered = in_scattered + light_here * scattering * seen * step as f32;
+        }
+        background * self.transmittance(distance, wavelength) + in_scattered
+    }
+}
+
+//Fraction of light scattered through an angle whose cosine is cos_theta, per steradian
+fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * std::f32::consts::PI * denominator * denominator.sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(anisotropy: &str) -> Result<Medium, serde_json::Error> {
+        serde_json::from_str(&format!(r#"{{
+            "absorption": {{ "red": 0.1, "green": 0.1, "blue": 0.1 }},
+            "scattering": {{ "red": 0.1, "green": 0.1, "blue": 0.1 }},
+            "anisotropy": {} }}"#, anisotropy))
+    }
+
+    #[test]
+    fn anisotropy_stays_within_the_open_range() {
+        for &g in &["-0.9", "0", "0.99"] {
+            assert!(read(g).is_ok(), "{}", g);
+        }
+        for &g in &["-1", "1", "1.5", "-3"] {
+            assert!(read(g).is_err(), "{}", g);
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use crate::vector3::Vector3;
+use crate::ray::Ray;
+use crate::scene::Intersectable;
+use crate::material::Material;
+use crate::material::TextureCoords;
+use crate::transform::Transform;
+use std::sync::Arc;
+
+const LEAF_SIZE: usize = 4;
+
+//Triangles with a normal and texture coords at every vertex, held in a bounding volume hierarchy so a ray only
+//tests the few triangles near its path. Meshes are not written in the scene directly; patches and subdivision
+//cages are tessellated into them while the scene is read.
+#[derive(Clone)]
+pub struct Mesh {
+    pub material: Material,
+    pub transform: Option<Transform>,
+    data: Arc<MeshData>,
+    hit: Option<usize>, //set on the copy an intersection keeps, the triangle the ray hit
+}
+
+struct MeshData {
+    positions: Vec<Vector3>,
+    normals: Vec<Vector3>,
+    uvs: Vec<(f64, f64)>,
+    triangles: Vec<[usize; 3]>, //reordered while building so that every leaf covers a run of them
+    nodes: Vec<Node>,
+}
+
+//A box round some triangles. A leaf has count > 0 and covers triangles[start..start + count], otherwise its
+//children are the node straight after it and the node at second
+struct Node {
+    min: Vector3,
+    max: Vector3,
+    start: usize,
+    count: usize,
+    second: usize,
+}
+
+impl Mesh {
+    //uvs may be empty, in which case the texture is projected onto the mesh from its center like onto a sphere
+    pub fn new(positions: Vec<Vector3>, normals: Vec<Vector3>, uvs: Vec<(f64, f64)>, triangles: Vec<[usize; 3]>,
+               material: Material, transform: Option<Transform>) -> Result<Mesh, String> {
+        if triangles.is_empty() {
+            return Err(String::from("a mesh needs at least one triangle"));
+        }
+        if triangles.iter().any(|t| t.iter().any(|&i| i >= positions.len())) {
+            return Err(String::from("a mesh triangle refers to a vertex that does not exist"));
+        }
+        let uvs = if uvs.is_empty() { spherical_uvs(&positions) } else { uvs };
+        let mut triangles = triangles;
+        let mut nodes = Vec::new();
+        let count = triangles.len();
+        build(&positions, &mut triangles, 0, count, &mut nodes);
+        Ok(Mesh {
+            material,
+            transform,
+            data: Arc::new(MeshData { positions, normals, uvs, triangles, nodes }),
+            hit: None,
+        })
+    }
+
+    //The mesh as seen by a ray that hit the given triangle, so questions about the hit need not search for it
+    pub fn on_triangle(&self, triangle: usize) -> Mesh {
+        Mesh { hit: Some(triangle), ..self.clone() }
+    }
+
+    pub fn surface_normal(&self, hit_point: &Vector3) -> Vector3 { //the vertex normals blended across the triangle
+        let (triangle, b) = self.triangle_at(hit_point);
+        let n = &self.data.normals;
+        (n[triangle[0]] * b[0] + n[triangle[1]] * b[1] + n[triangle[2]] * b[2]).normalize()
+    }
+
+    pub fn texture_coords(&self, hit_point: &Vector3) -> TextureCoords {
+        let (triangle, b) = self.triangle_at(hit_point);
+        let uv = &self.data.uvs;
+        TextureCoords {
+            x: (uv[triangle[0]].0 * b[0] + uv[triangle[1]].0 * b[1] + uv[triangle[2]].0 * b[2]) as f32,
+            y: (uv[triangle[0]].1 * b[0] + uv[triangle[1]].1 * b[1] + uv[triangle[2]].1 * b[2]) as f32,
+        }
+    }
+
+    //From how the texture coords change along the triangle's edges
+    pub fn tangents(&self, hit_point: &Vector3) -> (Vector3, Vector3) {
+        let (triangle, _) = self.triangle_at(hit_point);
+        let (p, uv) = (&self.data.positions, &self.data.uvs);
+        let (e1, e2) = (p[triangle[1]] - p[triangle[0]], p[triangle[2]] - p[triangle[0]]);
+        let (du1, dv1) = (uv[triangle[1]].0 - uv[triangle[0]].0, uv[triangle[1]].1 - uv[triangle[0]].1);
+        let (du2, dv2) = (uv[triangle[2]].0 - uv[triangle[0]].0, uv[triangle[2]].1 - uv[triangle[0]].1);
+        let determinant = du1 * dv2 - du2 * dv1;
+        if determinant.abs() < 1e-12 { //the texture is squashed flat over this triangle, any directions in it will do
+            let normal = e1.cross(&e2);
+            return (e1.normalize(), normal.cross(&e1).normalize());
+        }
+        let r = 1.0 / determinant;
+        ((e1 * dv2 - e2 * dv1) * r, (e2 * du1 - e1 * du2) * r)
+    }
+
+    //The triangle a point is on and the point's barycentric coords on it. That is the triangle the ray hit, unless the
+    //point is somewhere else on the mesh (e.g. where light entered it for subsurface scattering); then it is the one
+    //among those whose boxes hold the point that it lies closest to
+    fn triangle_at(&self, point: &Vector3) -> ([usize; 3], [f64; 3]) {
+        let data = &*self.data;
+        if let Some(index) = self.hit {
+            let triangle = data.triangles[index];
+            if let Some((weights, error)) = self.locate(&triangle, point) {
+                let (a, b, c) = (data.positions[triangle[0]], data.positions[triangle[1]], data.positions[triangle[2]]);
+                if error <= ((b - a).length().max((c - a).length())) * 1e-2 { //near enough for the small steps texture filtering takes
+                    return (triangle, weights);
+                }
+            }
+        }
+        let root = &data.nodes[0];
+        let tolerance = (root.max - root.min).length() * 1e-6 + 1e-9;
+        let mut best = (data.triangles[0], [1.0, 0.0, 0.0], f64::INFINITY);
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &data.nodes[index];
+            let outside = |v: f64, min: f64, max: f64| v < min - tolerance || v > max + tolerance;
+            if outside(point.x, node.min.x, node.max.x) || outside(point.y, node.min.y, node.max.y) || outside(point.z, node.min.z, node.max.z) {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(index + 1);
+                stack.push(node.second);
+                continue;
+            }
+            for triangle in &data.triangles[node.start..node.start + node.count] {
+                if let Some((weights, error)) = self.locate(triangle, point) {
+                    if error < best.2 {
+                        best = (*triangle, weights, error);
+                    }
+                }
+            }
+        }
+        (best.0, best.1)
+    }
+
+    //A point's barycentric coords on a triangle and how far it is off it, None for triangles with no area
+    fn locate(&self, triangle: &[usize; 3], point: &Vector3) -> Option<([f64; 3], f64)> {
+        let p = &self.data.positions;
+        let (a, b, c) = (p[triangle[0]], p[triangle[1]], p[triangle[2]]);
+        let normal = (b - a).cross(&(c - a));
+        let area = normal.dot(&normal);
+        if area < 1e-24 {
+            return None;
+        }
+        let off_plane = (*point - a).dot(&normal).abs() / area.sqrt();
+        let b1 = (*point - a).cross(&(c - a)).dot(&normal) / area;
+        let b2 = (b - a).cross(&(*point - a)).dot(&normal) / area;
+        let weights = [1.0 - b1 - b2, b1, b2];
+        let off_edge = weights.iter().map(|w| (-w).max(0.0)).sum::<f64>() * (b - a).length().max((c - a).length());
+        Some((weights, off_plane + off_edge))
+    }
+
+    //The nearest hit and the (index of the) triangle it is on
+    pub fn hit(&self, ray: &Ray) -> Option<(f64, usize)> {
+        let data = &*self.data;
+        let inverse = Vector3::new(1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z);
+        let mut nearest: Option<(f64, usize)> = None;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &data.nodes[index];
+            let limit = nearest.map_or(f64::INFINITY, |(t, _)| t);
+            if enters_box(&ray.origin, &inverse, &node.min, &node.max, limit).is_none() {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(index + 1);
+                stack.push(node.second);
+                continue;
+            }
+            for (offset, triangle) in data.triangles[node.start..node.start + node.count].iter().enumerate() {
+                let p = &data.positions;
+                if let Some(t) = intersect_triangle(&ray.origin, &ray.direction, &p[triangle[0]], &p[triangle[1]], &p[triangle[2]]) {
+                    if t < limit && nearest.is_none_or(|(n, _)| t < n) {
+                        nearest = Some((t, node.start + offset));
+                    }
+                }
+            }
+        }
+        nearest
+    }
+}
+
+fn spherical_uvs(positions: &[Vector3]) -> Vec<(f64, f64)> {
+    let sum = positions.iter().fold(Vector3::zero(), |sum, &p| sum + p);
+    let center = sum * (1.0 / positions.len() as f64);
+    positions.iter().map(|&p| {
+        let offset = p - center;
+        let length = offset.length().max(1e-12);
+        ((1.0 + offset.z.atan2(offset.x) / std::f64::consts::PI) * 0.5, (offset.y / length).clamp(-1.0, 1.0).acos() / std::f64::consts::PI)
+    }).collect()
+}
+
+//Splits the triangles at the median of their centers along the box's longest side, until few enough are left
+fn build(positions: &[Vector3], triangles: &mut [[usize; 3]], start: usize, count: usize, nodes: &mut Vec<Node>) -> usize {
+    let slice = &mut triangles[start..start + count];
+    let (mut min, mut max) = (Vector3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY), Vector3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY));
+    for triangle in slice.iter() {
+        for &i in triangle {
+            let p = positions[i];
+            min = Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+    }
+    let index = nodes.len();
+    nodes.push(Node { min, max, start, count, second: 0 });
+    if count <= LEAF_SIZE {
+        return index;
+    }
+    let extent = max - min;
+    let center = |t: &[usize; 3]| {
+        let c = positions[t[0]] + positions[t[1]] + positions[t[2]];
+        if extent.x >= extent.y && extent.x >= extent.z { c.x } else if extent.y >= extent.z { c.y } else { c.z }
+    };
+    slice.sort_by(|a, b| center(a).partial_cmp(&center(b)).unwrap_or(std::cmp::Ordering::Equal));
+    let half = count / 2;
+    build(positions, triangles, start, half, nodes);
+    let second = build(positions, triangles, start + half, count - half, nodes);
+    nodes[index].count = 0;
+    nodes[index].second = second;
+    index
+}
+
+//Where a ray enters a box, if it does before limit
+fn enters_box(origin: &Vector3, inverse: &Vector3, min: &Vector3, max: &Vector3, limit: f64) -> Option<f64> {
+    let (mut near, mut far) = (0.0f64, limit);
+    for &(o, inv, lo, hi) in &[(origin.x, inverse.x, min.x, max.x), (origin.y, inverse.y, min.y, max.y), (origin.z, inverse.z, min.z, max.z)] {
+        let (t0, t1) = ((lo - o) * inv, (hi - o) * inv);
+        near = near.max(t0.min(t1));
+        far = far.min(t0.max(t1));
+    }
+    if near <= far { Some(near) } else { None }
+}
+
+//Möller-Trumbore, hit from either side
+pub fn intersect_triangle(origin: &Vector3, direction: &Vector3, a: &Vector3, b: &Vector3, c: &Vector3) -> Option<f64> {
+    let (edge1, edge2) = (*b - *a, *c - *a);
+    let p = direction.cross(&edge2);
+    let determinant = edge1.dot(&p);
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    let inverse = 1.0 / determinant;
+    let to_origin = *origin - *a;
+    let u = to_origin.dot(&p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = to_origin.cross(&edge1);
+    let v = direction.dot(&q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = edge2.dot(&q) * inverse;
+    if t >= 0.0 { Some(t) } else { None }
+}
+
+impl Intersectable for Mesh {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        self.hit(ray).map(|(distance, _)| distance)
+    }
+}
+
+//Area weighted average of the normals of the faces round each vertex, for meshes that only have positions
+pub fn smooth_normals(positions: &[Vector3], triangles: &[[usize; 3]]) -> Vec<Vector3> {
+    let mut normals = vec![Vector3::zero(); positions.len()];
+    for t in triangles {
+        let face = (positions[t[1]] - positions[t[0]]).cross(&(positions[t[2]] - positions[t[0]]));
+        for &i in t {
+            normals[i] = normals[i] + face;
+        }
+    }
+    normals.iter().map(|n| if n.length() < 1e-12 { Vector3::new(0.0, 1.0, 0.0) } else { n.normalize() }).collect()
+}
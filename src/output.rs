@@ -0,0 +1,171 @@
+use image::{DynamicImage, ImageResult, ColorType, GenericImageView};
+use image::png::PNGEncoder;
+use image::jpeg::JPEGEncoder;
+use image::bmp::BMPEncoder;
+use image::tiff::TiffEncoder;
+use serde::Deserialize;
+use std::io::Cursor;
+
+const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+//What the client asked for in the query string of /trace e.g. /trace?format=jpeg&quality=80 or /trace?format=png&depth=16
+//Anything given here wins over the Accept header.
+#[derive(Debug, Default, Deserialize)]
+pub struct OutputQuery {
+    pub format: Option<String>,
+    pub quality: Option<u8>,
+    pub depth: Option<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Png { sixteen_bit: bool },
+    Jpeg { quality: u8 },
+    WebP { quality: Option<u8> }, //no quality means lossless
+    Tiff { sixteen_bit: bool },
+    Bmp,
+}
+
+impl OutputFormat {
+    pub fn content_type(&self) -> &'static str {
+        match *self {
+            OutputFormat::Png { .. } => "image/png",
+            OutputFormat::Jpeg { .. } => "image/jpeg",
+            OutputFormat::WebP { .. } => "image/webp",
+            OutputFormat::Tiff { .. } => "image/tiff",
+            OutputFormat::Bmp => "image/bmp",
+        }
+    }
+
+    fn from_name(name: &str, query: &OutputQuery) -> Option<OutputFormat> {
+        let sixteen_bit = query.depth == Some(16);
+        match name.trim().to_lowercase().as_str() {
+            "png" | "image/png" => Some(OutputFormat::Png { sixteen_bit }),
+            "png16" => Some(OutputFormat::Png { sixteen_bit: true }),
+            "jpeg" | "jpg" | "image/jpeg" | "image/jpg" => Some(OutputFormat::Jpeg {
+                quality: query.quality.unwrap_or(DEFAULT_JPEG_QUALITY).clamp(1, 100),
+            }),
+            "webp" | "image/webp" => Some(OutputFormat::WebP {
+                quality: query.quality.map(|q| q.clamp(1, 100)),
+            }),
+            "tiff" | "tif" | "image/tiff" => Some(OutputFormat::Tiff { sixteen_bit }),
+            "tiff16" => Some(OutputFormat::Tiff { sixteen_bit: true }),
+            "bmp" | "image/bmp" => Some(OutputFormat::Bmp),
+            "image/*" | "*/*" => Some(OutputFormat::Png { sixteen_bit }),
+            _ => None,
+        }
+    }
+}
+
+//Picks the output format from the query string first, then the Accept header (highest q value that we can encode),
+//falling back to an 8 bit png when the client does not say anything. Err carries a message for a 406 response.
+pub fn negotiate(accept: Option<&str>, query: &OutputQuery) -> Result<OutputFormat, String> {
+    if let Some(name) = &query.format {
+        return OutputFormat::from_name(name, query)
+            .ok_or_else(|| format!("Unsupported output format: {}", name));
+    }
+    let accept = match accept {
+        Some(a) if !a.trim().is_empty() => a,
+        _ => return Ok(OutputFormat::Png { sixteen_bit: query.depth == Some(16) }),
+    };
+    let mut ranges: Vec<(&str, f32)> = accept.split(',')
+        .map(|range| {
+            let mut parts = range.split(';');
+            let media_type = parts.next().unwrap_or("").trim();
+            let q = parts
+                .filter_map(|p| {
+                    p.trim().strip_prefix("q=").and_then(|q| q.parse::<f32>().ok())
+                })
+                .next()
+                .unwrap_or(1.0);
+            (media_type, q)
+        })
+        .filter(|&(_, q)| q > 0.0)
+        .collect();
+    ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal)); //stable, so ties keep the client's order
+    ranges.iter()
+        .filter_map(|&(media_type, _)| OutputFormat::from_name(media_type, query))
+        .next()
+        .ok_or_else(|| format!("None of the accepted types can be produced: {}", accept))
+}
+
+//Encodes the rendered image, which is kept at 16 bits per channel so both 8 and 16 bit outputs come from the same render
+pub fn encode(img: &DynamicImage, format: OutputFormat) -> ImageResult<Vec<u8>> {
+    let (width, height) = img.dimensions();
+    let mut data: Vec<u8> = Vec::new();
+    match format {
+        OutputFormat::Png { sixteen_bit: true } => {
+            let bytes: Vec<u8> = rgb16_samples(img).iter().flat_map(|v| v.to_be_bytes().to_vec()).collect(); //png stores samples big endian
+            PNGEncoder::new(&mut data).encode(&bytes, width, height, ColorType::Rgb16)?;
+        }
+        OutputFormat::Png { sixteen_bit: false } => {
+            PNGEncoder::new(&mut data).encode(&img.to_rgb().into_raw(), width, height, ColorType::Rgb8)?;
+        }
+        OutputFormat::Jpeg { quality } => {
+            JPEGEncoder::new_with_quality(&mut data, quality).encode(&img.to_rgb().into_raw(), width, height, ColorType::Rgb8)?;
+        }
+        OutputFormat::WebP { quality } => {
+            let rgb = img.to_rgb().into_raw();
+            let encoder = webp::Encoder::from_rgb(&rgb, width, height);
+            let memory = match quality {
+                Some(q) => encoder.encode(q as f32),
+                None => encoder.encode_lossless(),
+            };
+            data.extend_from_slice(&memory);
+        }
+        OutputFormat::Tiff { sixteen_bit } => {
+            let mut cursor = Cursor::new(&mut data);
+            if sixteen_bit {
+                let bytes: Vec<u8> = rgb16_samples(img).iter().flat_map(|v| v.to_ne_bytes().to_vec()).collect(); //tiff takes native endian samples
+                TiffEncoder::new(&mut cursor).encode(&bytes, width, height, ColorType::Rgb16)?;
+            } else {
+                TiffEncoder::new(&mut cursor).encode(&img.to_rgb().into_raw(), width, height, ColorType::Rgb8)?;
+            }
+        }
+        OutputFormat::Bmp => {
+            BMPEncoder::new(&mut data).encode(&img.to_rgb().into_raw(), width, height, ColorType::Rgb8)?;
+        }
+    }
+    Ok(data)
+}
+
+fn rgb16_samples(img: &DynamicImage) -> Vec<u16> {
+    match img.as_rgb16() {
+        Some(buffer) => buffer.iter().cloned().collect(),
+        None => img.to_rgb().into_raw().iter().map(|&v| v as u16 * 257).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(format: Option<&str>, quality: Option<u8>, depth: Option<u8>) -> OutputQuery {
+        OutputQuery { format: format.map(String::from), quality, depth }
+    }
+
+    #[test]
+    fn defaults_to_png() {
+        assert_eq!(negotiate(None, &OutputQuery::default()), Ok(OutputFormat::Png { sixteen_bit: false }));
+        assert_eq!(negotiate(Some("  "), &query(None, None, Some(16))), Ok(OutputFormat::Png { sixteen_bit: true }));
+        assert_eq!(negotiate(Some("*/*"), &OutputQuery::default()), Ok(OutputFormat::Png { sixteen_bit: false }));
+    }
+
+    #[test]
+    fn query_wins_over_accept() {
+        let format = negotiate(Some("image/png"), &query(Some("jpg"), Some(0), None));
+        assert_eq!(format, Ok(OutputFormat::Jpeg { quality: 1 }));
+        assert_eq!(negotiate(None, &query(Some("webp"), None, None)), Ok(OutputFormat::WebP { quality: None }));
+        assert_eq!(negotiate(None, &query(Some("tiff16"), None, None)), Ok(OutputFormat::Tiff { sixteen_bit: true }));
+        assert!(negotiate(None, &query(Some("gif"), None, None)).is_err());
+    }
+
+    #[test]
+    fn accept_is_ordered_by_q() {
+        let accept = Some("image/png;q=0.5, image/gif, image/jpeg;q=0.8");
+        assert_eq!(negotiate(accept, &OutputQuery::default()), Ok(OutputFormat::Jpeg { quality: DEFAULT_JPEG_QUALITY }));
+        assert_eq!(negotiate(Some("image/bmp, image/tiff"), &OutputQuery::default()), Ok(OutputFormat::Bmp)); //ties keep the client's order
+        assert_eq!(negotiate(Some("image/jpeg;q=0, image/webp"), &query(None, Some(200), None)), Ok(OutputFormat::WebP { quality: Some(100) }));
+        assert!(negotiate(Some("image/gif, image/jpeg;q=0"), &OutputQuery::default()).is_err());
+    }
+}
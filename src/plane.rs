@@ -4,13 +4,16 @@ use crate::color::Color;
 use crate::scene::Intersectable;
 use crate::material::Material;
 use crate::material::TextureCoords;
+use crate::transform::Transform;
 use serde::{Serialize, Deserialize};
 
 #[derive(Clone, Deserialize)]
 pub struct Plane {
     pub p0: Vector3,
     pub normal: Vector3,
-    pub material: Material
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
 }
 
 impl Plane {
@@ -19,6 +22,20 @@ impl Plane {
     }
 
     pub fn texture_coords(&self, hit_point: &Vector3) -> TextureCoords  {
+        let (x_axis, y_axis) = self.axes();
+        let hit_vec = *hit_point - self.p0;
+        TextureCoords {
+            x: hit_vec.dot(&x_axis) as f32,
+            y: hit_vec.dot(&y_axis) as f32,
+        }
+    }
+
+    pub fn tangents(&self, _: &Vector3) -> (Vector3, Vector3) {
+        let (x_axis, y_axis) = self.axes();
+        (x_axis.normalize(), y_axis.normalize())
+    }
+
+    fn axes(&self) -> (Vector3, Vector3) {
         let mut x_axis = self.normal.cross(&Vector3 {
             x: 0.0,
             y: 0.0,
@@ -32,11 +49,7 @@ impl Plane {
             });
         }
         let y_axis = self.normal.cross(&x_axis);
-        let hit_vec = *hit_point - self.p0;
-        TextureCoords {
-            x: hit_vec.dot(&x_axis) as f32,
-            y: hit_vec.dot(&y_axis) as f32,
-        }
+        (x_axis, y_axis)
     }
 }
 
@@ -0,0 +1,328 @@
+use crate::color::Color;
+use crate::vector3::Vector3;
+use crate::material::TextureCoords;
+use serde::Deserialize;
+
+//Procedural colorations are functions of a point instead of image lookups. The point is either the
+//element's texture coordinates (u, v, 0) or the hit point in world units, taken before the element's transform
+//so that the pattern moves with the element.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+pub enum Space {
+    #[default]
+    Uv,
+    World,
+}
+
+impl Space {
+    fn point(&self, texture_coords: &TextureCoords, hit_point: &Vector3) -> Vector3 {
+        match *self {
+            Space::Uv => Vector3::new(texture_coords.x as f64, texture_coords.y as f64, 0.0),
+            Space::World => *hit_point,
+        }
+    }
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+fn default_octaves() -> u32 {
+    6
+}
+
+fn lerp(a: Color, b: Color, t: f32) -> Color {
+    let t = t.clamp(0.0, 1.0);
+    a * (1.0 - t) + b * t
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Checker {
+    pub even: Color,
+    pub odd: Color,
+    #[serde(default = "one")]
+    pub scale: f32, //squares per unit
+    #[serde(default)]
+    pub space: Space,
+}
+
+impl Checker {
+    pub fn color(&self, texture_coords: &TextureCoords, hit_point: &Vector3) -> Color {
+        let p = self.space.point(texture_coords, hit_point) * self.scale as f64;
+        let sum = match self.space {
+            Space::Uv => p.x.floor() + p.y.floor(),
+            Space::World => p.x.floor() + p.y.floor() + p.z.floor(),
+        };
+        if (sum as i64).rem_euclid(2) == 0 { self.even } else { self.odd }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Noise {
+    pub low: Color,
+    pub high: Color,
+    #[serde(default = "one")]
+    pub scale: f32,
+    #[serde(default)]
+    pub space: Space,
+}
+
+impl Noise {
+    pub fn color(&self, texture_coords: &TextureCoords, hit_point: &Vector3) -> Color {
+        let p = self.space.point(texture_coords, hit_point) * self.scale as f64;
+        lerp(self.low, self.high, (perlin(p) as f32 + 1.0) * 0.5)
+    }
+}
+
+//Fractal brownian motion turbulence: octaves of |noise|, each at twice the frequency and half the amplitude of the last
+#[derive(Clone, Debug, Deserialize)]
+pub struct Turbulence {
+    pub low: Color,
+    pub high: Color,
+    #[serde(default = "one")]
+    pub scale: f32,
+    #[serde(default = "default_octaves")]
+    pub octaves: u32,
+    #[serde(default)]
+    pub space: Space,
+}
+
+impl Turbulence {
+    pub fn color(&self, texture_coords: &TextureCoords, hit_point: &Vector3) -> Color {
+        let p = self.space.point(texture_coords, hit_point) * self.scale as f64;
+        lerp(self.low, self.high, turbulence(p, self.octaves) as f32)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Marble {
+    pub base: Color,
+    pub vein: Color,
+    #[serde(default = "one")]
+    pub scale: f32, //how many veins per unit along x
+    #[serde(default = "default_distortion")]
+    pub distortion: f32, //how strongly turbulence bends the veins
+    #[serde(default = "default_octaves")]
+    pub octaves: u32,
+    #[serde(default = "world")]
+    pub space: Space,
+}
+
+fn default_distortion() -> f32 {
+    5.0
+}
+
+fn world() -> Space {
+    Space::World
+}
+
+impl Marble {
+    pub fn color(&self, texture_coords: &TextureCoords, hit_point: &Vector3) -> Color {
+        let p = self.space.point(texture_coords, hit_point) * self.scale as f64;
+        let phase = p.x * std::f64::consts::PI + self.distortion as f64 * turbulence(p, self.octaves);
+        let t = 1.0 - phase.sin().abs().powf(0.5); //sharpen the sine into thin veins
+        lerp(self.base, self.vein, t as f32)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Wood {
+    pub light: Color,
+    pub dark: Color,
+    #[serde(default = "one")]
+    pub scale: f32, //rings per unit of distance from the y axis
+    #[serde(default = "default_grain")]
+    pub grain: f32, //how much noise wobbles the rings
+    #[serde(default = "world")]
+    pub space: Space,
+}
+
+fn default_grain() -> f32 {
+    0.3
+}
+
+impl Wood {
+    pub fn color(&self, texture_coords: &TextureCoords, hit_point: &Vector3) -> Color {
+        let p = self.space.point(texture_coords, hit_point) * self.scale as f64;
+        let distance = match self.space {
+            Space::Uv => p.x,
+            Space::World => (p.x * p.x + p.z * p.z).sqrt(),
+        };
+        let rings = distance + self.grain as f64 * perlin(p * 2.0);
+        let t = rings - rings.floor();
+        lerp(self.light, self.dark, (t * t) as f32)
+    }
+}
+
+//A linear blend from `from` to `to` along `direction`, reaching `to` one unit of distance along it
+#[derive(Clone, Debug, Deserialize)]
+pub struct Gradient {
+    pub from: Color,
+    pub to: Color,
+    #[serde(default = "x_axis")]
+    pub direction: Vector3,
+    #[serde(default)]
+    pub space: Space,
+}
+
+fn x_axis() -> Vector3 {
+    Vector3::new(1.0, 0.0, 0.0)
+}
+
+impl Gradient {
+    pub fn color(&self, texture_coords: &TextureCoords, hit_point: &Vector3) -> Color {
+        let p = self.space.point(texture_coords, hit_point);
+        lerp(self.from, self.to, p.dot(&self.direction) as f32)
+    }
+}
+
+pub fn turbulence(p: Vector3, octaves: u32) -> f64 { //sum of |noise| over octaves, normalised to roughly 0..1
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut total = 0.0;
+    let mut point = p;
+    for _ in 0..octaves.max(1) {
+        sum += perlin(point).abs() * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+        point = point * 2.0;
+    }
+    sum / total
+}
+
+//Ken Perlin's improved noise (2002). Returns values in roughly -1..1, zero at integer lattice points.
+pub fn perlin(p: Vector3) -> f64 {
+    let (xf, yf, zf) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let (x, y, z) = ((xf as i64 & 255) as usize, (yf as i64 & 255) as usize, (zf as i64 & 255) as usize);
+    let (fx, fy, fz) = (p.x - xf, p.y - yf, p.z - zf);
+    let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+
+    let perm = |i: usize| PERMUTATION[i & 255] as usize;
+    let a = perm(x) + y;
+    let aa = perm(a) + z;
+    let ab = perm(a + 1) + z;
+    let b = perm(x + 1) + y;
+    let ba = perm(b) + z;
+    let bb = perm(b + 1) + z;
+
+    lerp_f(w,
+        lerp_f(v,
+            lerp_f(u, grad(perm(aa), fx, fy, fz), grad(perm(ba), fx - 1.0, fy, fz)),
+            lerp_f(u, grad(perm(ab), fx, fy - 1.0, fz), grad(perm(bb), fx - 1.0, fy - 1.0, fz))),
+        lerp_f(v,
+            lerp_f(u, grad(perm(aa + 1), fx, fy, fz - 1.0), grad(perm(ba + 1), fx - 1.0, fy, fz - 1.0)),
+            lerp_f(u, grad(perm(ab + 1), fx, fy - 1.0, fz - 1.0), grad(perm(bb + 1), fx - 1.0, fy - 1.0, fz - 1.0))))
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp_f(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+fn grad(hash: usize, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+const PERMUTATION: [u8; 256] = [
+    151, 160, 137, 91, 90, 15, 131, 13, 201, 95, 96, 53, 194, 233, 7, 225, 140, 36, 103, 30, 69, 142, 8, 99, 37, 240, 21, 10, 23,
+    190, 6, 148, 247, 120, 234, 75, 0, 26, 197, 62, 94, 252, 219, 203, 117, 35, 11, 32, 57, 177, 33, 88, 237, 149, 56, 87, 174, 20,
+    125, 136, 171, 168, 68, 175, 74, 165, 71, 134, 139, 48, 27, 166, 77, 146, 158, 231, 83, 111, 229, 122, 60, 211, 133, 230, 220,
+    105, 92, 41, 55, 46, 245, 40, 244, 102, 143, 54, 65, 25, 63, 161, 1, 216, 80, 73, 209, 76, 132, 187, 208, 89, 18, 169, 200, 196,
+    135, 130, 116, 188, 159, 86, 164, 100, 109, 198, 173, 186, 3, 64, 52, 217, 226, 250, 124, 123, 5, 202, 38, 147, 118, 126, 255,
+    82, 85, 212, 207, 206, 59, 227, 47, 16, 58, 17, 182, 189, 28, 42, 223, 183, 170, 213, 119, 248, 152, 2, 44, 154, 163, 70, 221,
+    153, 101, 155, 167, 43, 172, 9, 129, 22, 39, 253, 19, 98, 108, 110, 79, 113, 224, 232, 178, 185, 112, 104, 218, 246, 97, 228,
+    251, 34, 242, 193, 238, 210, 144, 12, 191, 179, 162, 241, 81, 51, 145, 235, 249, 14, 239, 107, 49, 192, 214, 31, 181, 199, 106,
+    157, 184, 84, 204, 176, 115, 121, 50, 45, 127, 4, 150, 254, 138, 236, 205, 93, 222, 114, 67, 29, 24, 72, 243, 141, 128, 195, 78,
+    66, 215, 61, 156, 180,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Color = Color { red: 0.0, green: 0.0, blue: 0.0 };
+    const WHITE: Color = Color { red: 1.0, green: 1.0, blue: 1.0 };
+
+    //Points scattered over a few lattice cells either side of the origin, away from the lattice itself
+    fn points() -> impl Iterator<Item = Vector3> {
+        let mut seed: u64 = 7;
+        (0..5000).map(move |_| {
+            let mut next = || {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                (seed >> 11) as f64 / (1u64 << 53) as f64 * 16.0 - 8.0
+            };
+            Vector3::new(next(), next(), next())
+        })
+    }
+
+    #[test]
+    fn checker_alternates_between_neighbours() {
+        let checker = Checker { even: BLACK, odd: WHITE, scale: 2.0, space: Space::Uv };
+        let at = |x, y| checker.color(&TextureCoords { x, y }, &Vector3::zero()).red;
+        assert_eq!(at(0.1, 0.1), 0.0);
+        assert_eq!(at(0.6, 0.1), 1.0);
+        assert_eq!(at(0.1, 0.6), 1.0);
+        assert_eq!(at(0.6, 0.6), 0.0);
+        assert_eq!(at(-0.1, 0.1), 1.0); //keeps alternating below zero
+        assert_eq!(at(-0.1, -0.1), 0.0);
+
+        let checker = Checker { space: Space::World, ..checker };
+        let at = |x, y, z| checker.color(&TextureCoords { x: 0.0, y: 0.0 }, &Vector3::new(x, y, z)).red;
+        assert_eq!(at(0.1, 0.1, 0.1), 0.0);
+        assert_eq!(at(0.1, 0.1, 0.6), 1.0);
+        assert_eq!(at(0.6, 0.6, 0.6), 1.0);
+        assert_eq!(at(-0.1, -0.1, -0.1), 1.0);
+    }
+
+    #[test]
+    fn perlin_is_zero_on_the_lattice() {
+        for x in -3..4 {
+            for y in -3..4 {
+                for z in -3..4 {
+                    let p = Vector3::new(x as f64, y as f64, z as f64);
+                    assert!(perlin(p).abs() < 1e-12, "{:?}", p);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn perlin_stays_in_range_and_varies() {
+        let values: Vec<f64> = points().map(perlin).collect();
+        assert!(values.iter().all(|v| v.abs() <= 1.1), "{:?}", values.iter().cloned().fold(0.0, |a: f64, b| a.max(b.abs())));
+        assert!(values.iter().any(|&v| v > 0.3) && values.iter().any(|&v| v < -0.3));
+        let p = Vector3::new(0.3, 1.7, -2.2);
+        assert!((perlin(p) - perlin(p + Vector3::new(1e-6, 0.0, 0.0))).abs() < 1e-4); //smooth
+    }
+
+    #[test]
+    fn turbulence_stays_in_range() {
+        for p in points() {
+            let t = turbulence(p, 6);
+            assert!((0.0..=1.1).contains(&t), "{:?} {}", p, t);
+        }
+        assert_eq!(turbulence(Vector3::new(0.5, 0.25, 0.75), 0), turbulence(Vector3::new(0.5, 0.25, 0.75), 1)); //at least one octave
+    }
+
+    #[test]
+    fn marble_and_wood_blend_between_their_colors() {
+        let coords = TextureCoords { x: 0.0, y: 0.0 };
+        let marble = Marble { base: BLACK, vein: WHITE, scale: 1.0, distortion: 5.0, octaves: 4, space: Space::World };
+        let wood = Wood { light: BLACK, dark: WHITE, scale: 1.0, grain: 0.3, space: Space::World };
+        for p in points() {
+            for &c in &[marble.color(&coords, &p), wood.color(&coords, &p)] {
+                assert!((0.0..=1.0).contains(&c.red) && c.red == c.green && c.green == c.blue, "{:?} {:?}", p, c);
+            }
+        }
+        let marble = Marble { distortion: 0.0, ..marble };
+        assert!((marble.color(&coords, &Vector3::new(1.0, 0.3, 0.3)).red - 1.0).abs() < 1e-6); //veins where the sine crosses zero
+        assert!(marble.color(&coords, &Vector3::new(0.5, 0.3, 0.3)).red.abs() < 1e-6);
+        let wood = Wood { grain: 0.0, ..wood };
+        assert!((wood.color(&coords, &Vector3::new(0.3, 5.0, 0.4)).red - 0.25).abs() < 1e-6); //half way through a ring
+    }
+}
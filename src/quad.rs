@@ -0,0 +1,96 @@
+use crate::vector3::Vector3;
+use crate::ray::Ray;
+use crate::scene::Intersectable;
+use crate::material::Material;
+use crate::material::TextureCoords;
+use crate::transform::Transform;
+use crate::cuboid::rotation_axes;
+use serde::{Deserialize, Deserializer};
+
+//A parallelogram with one corner at origin and sides u and v. It is hit from both sides, its normal turned to face the
+//ray when shading, and the texture is stretched over it once: x along u and y along v.
+#[derive(Clone, Deserialize)]
+pub struct Quad {
+    pub origin: Vector3,
+    pub u: Vector3,
+    pub v: Vector3,
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+}
+
+//A rectangle is written in the scene by its center and size instead, lying in the xz plane facing up before rotation
+//(degrees about x, then y, then z) e.g. a wall is { "center": ..., "width": 4, "height": 3, "rotation": { "x": 90, ... } }
+#[derive(Deserialize)]
+struct RectangleDesc {
+    center: Vector3,
+    width: f64,
+    height: f64,
+    #[serde(default = "Vector3::zero")]
+    rotation: Vector3,
+    material: Material,
+    #[serde(default)]
+    transform: Option<Transform>,
+}
+
+pub fn rectangle<'de, D>(deserializer: D) -> Result<Quad, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let desc = RectangleDesc::deserialize(deserializer)?;
+    let axes = rotation_axes(&desc.rotation);
+    let u = axes[0] * desc.width;
+    let v = axes[2] * -desc.height; //so that u x v points up
+    Ok(Quad {
+        origin: desc.center - (u + v) * 0.5,
+        u,
+        v,
+        material: desc.material,
+        transform: desc.transform,
+    })
+}
+
+impl Quad {
+    pub fn surface_normal(&self, _: &Vector3) -> Vector3 {
+        self.u.cross(&self.v).normalize()
+    }
+
+    pub fn texture_coords(&self, hit_point: &Vector3) -> TextureCoords {
+        let (a, b) = self.coords(hit_point);
+        TextureCoords {
+            x: a as f32,
+            y: b as f32,
+        }
+    }
+
+    pub fn tangents(&self, _: &Vector3) -> (Vector3, Vector3) {
+        (self.u.normalize(), self.v.normalize())
+    }
+
+    //How far along u and v a point in the quad's plane is, as fractions of their lengths
+    fn coords(&self, point: &Vector3) -> (f64, f64) {
+        let normal = self.u.cross(&self.v);
+        let offset = *point - self.origin;
+        let area = normal.dot(&normal);
+        (offset.cross(&self.v).dot(&normal) / area, self.u.cross(&offset).dot(&normal) / area)
+    }
+}
+
+impl Intersectable for Quad {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let normal = self.u.cross(&self.v).normalize(); //normalised so that the edge on test does not depend on the quad's size
+        let denom = normal.dot(&ray.direction);
+        if denom.abs() < 1e-6 {
+            return None;
+        }
+        let distance = (self.origin - ray.origin).dot(&normal) / denom;
+        if distance < 0.0 {
+            return None;
+        }
+        let (a, b) = self.coords(&(ray.origin + ray.direction * distance));
+        if !(0.0..=1.0).contains(&a) || !(0.0..=1.0).contains(&b) {
+            return None;
+        }
+        Some(distance)
+    }
+}
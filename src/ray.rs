@@ -6,35 +6,78 @@ use crate::scene::Scene;
 pub struct Ray {
     pub origin: Vector3,
     pub direction: Vector3,
+    pub differential: Option<Differential>,
+    pub time: f64, //when in the frame the ray is traced, for things that move. Rays it spawns keep it
+}
+
+//The rays through the neighbouring pixels to the right and below. Used to work out how much of a texture a pixel covers.
+//Only prime rays carry them; reflected, refracted and shadow rays do not.
+#[derive(Clone, Copy)]
+pub struct Differential {
+    pub rx_origin: Vector3,
+    pub rx_direction: Vector3,
+    pub ry_origin: Vector3,
+    pub ry_direction: Vector3,
 }
 
 impl Ray {
-    pub fn create_prime(x: u32, y: u32, scene: &Scene) -> Ray {
+    //lens is the point of the camera's lens the ray leaves from, (0, 0) for a pinhole camera
+    pub fn create_prime(x: u32, y: u32, time: f64, lens: (f64, f64), scene: &Scene) -> Ray {
+        let camera = scene.camera.transform.as_ref().map(|t| t.at(time));
+        let lens_point = Vector3::new(lens.0, lens.1, 0.0);
+        let origin = match camera {
+            Some(ref t) => t.point_to_world(&lens_point),
+            None => lens_point,
+        };
+        let direction = |x: f64, y: f64| {
+            let mut direction = Ray::prime_direction(x, y, scene);
+            if lens != (0.0, 0.0) { //aimed from the lens at where the pinhole ray meets the plane in focus
+                let focus_distance = scene.camera.focus_distance;
+                direction = (direction * (focus_distance / -direction.z) - lens_point).normalize();
+            }
+            match camera {
+                Some(ref t) => t.direction_to_world(&direction).normalize(),
+                None => direction,
+            }
+        };
+        Ray {
+            origin,
+            direction: direction(x as f64, y as f64),
+            differential: Some(Differential {
+                rx_origin: origin,
+                rx_direction: direction(x as f64 + 1.0, y as f64),
+                ry_origin: origin,
+                ry_direction: direction(x as f64, y as f64 + 1.0),
+            }),
+            time,
+        }
+    }
+
+    fn prime_direction(x: f64, y: f64, scene: &Scene) -> Vector3 {
         assert!(scene.width > scene.height);
         let fov_adjustment = (scene.fov.to_radians() / 2.0).tan();
         let aspect_ratio = (scene.width as f64) / (scene.height as f64);
-        let sensor_x = ((((x as f64 + 0.5) / scene.width as f64) * 2.0 - 1.0) * aspect_ratio) * fov_adjustment;
-        let sensor_y = (1.0 - ((y as f64 + 0.5) / scene.height as f64) * 2.0) * fov_adjustment;
-    
-        Ray {
-            origin: Vector3::zero(),
-            direction: Vector3 {
-                    x: sensor_x,
-                    y: sensor_y,
-                    z: -1.0,
-                }
-                .normalize(),
-        }
+        let sensor_x = ((((x + 0.5) / scene.width as f64) * 2.0 - 1.0) * aspect_ratio) * fov_adjustment;
+        let sensor_y = (1.0 - ((y + 0.5) / scene.height as f64) * 2.0) * fov_adjustment;
+
+        Vector3 {
+                x: sensor_x,
+                y: sensor_y,
+                z: -1.0,
+            }
+            .normalize()
     }
 
-    pub fn create_reflection(normal: Vector3, incident: Vector3, intersection: Vector3, bias: f64) -> Ray { //for reflection
+    pub fn create_reflection(normal: Vector3, incident: Vector3, intersection: Vector3, bias: f64, time: f64) -> Ray { //for reflection
         Ray {
             origin: intersection + (normal * bias),
             direction: incident - (normal * 2.0 * incident.dot(&normal) ),
+            differential: None,
+            time,
         }
     }
 
-    pub fn create_transmission(normal: Vector3, incident: Vector3, intersection: Vector3, bias: f64, index: f32) -> Option<Ray> { //for refraction
+    pub fn create_transmission(normal: Vector3, incident: Vector3, intersection: Vector3, bias: f64, index: f32, time: f64) -> Option<Ray> { //for refraction
         let mut ref_n = normal;
         let mut eta_t = index as f64;
         let mut eta_i = 1.0f64;
@@ -57,6 +100,8 @@ impl Ray {
             Some(Ray {
                 origin: intersection + (ref_n * -bias),
                 direction: (incident + ref_n * i_dot_n ) * eta - ref_n * k.sqrt(),
+                differential: None,
+                time,
             })
         }
     }
@@ -1,17 +1,56 @@
 use crate::vector3::Vector3;
 use crate::sphere::Sphere;
 use crate::plane::Plane;
-use crate::ray::Ray;
-use crate::color::Color;
+use crate::cuboid::Cuboid;
+use crate::quad::{self, Quad};
+use crate::cylinder::Cylinder;
+use crate::cone::Cone;
+use crate::disk::Disk;
+use crate::torus::Torus;
+use crate::instance::Instance;
+use crate::csg::Csg;
+use crate::sdf::DistanceField;
+use crate::heightfield::Heightfield;
+use crate::mesh::Mesh;
+use crate::bezier;
+use crate::subdivision;
+use crate::transform::Transform;
+use crate::group;
+use crate::ray::{Ray, Differential};
+use crate::color::{Color, ColorSpace};
 use crate::light::Light;
 use crate::material::TextureCoords;
-use crate::material::Material;
-use serde::{Serialize, Deserialize};
+use crate::material::{Material, SurfaceType};
+use crate::texture::Footprint;
+use crate::medium::Fog;
+use crate::camera::Camera;
+use crate::spectrum;
+use serde::{Serialize, Deserialize, Deserializer};
+use serde::de::Error;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::borrow::Cow;
 
 #[derive(Clone, Deserialize)]
 pub enum Element {
     Sphere(Sphere),
     Plane(Plane),
+    Box(Cuboid),
+    Quad(Quad),
+    #[serde(deserialize_with = "quad::rectangle")]
+    Rectangle(Quad),
+    Cylinder(Cylinder),
+    Cone(Cone),
+    Disk(Disk),
+    Torus(Torus),
+    Instance(Instance),
+    Csg(Csg),
+    Sdf(DistanceField),
+    Heightfield(Heightfield),
+    #[serde(deserialize_with = "bezier::patches")]
+    Patches(Mesh),
+    #[serde(deserialize_with = "subdivision::cage")]
+    Subdivision(Mesh),
 }
 
 impl Element {
@@ -21,44 +60,268 @@ impl Element {
     //We must make sure the field: "color" in Sphere/Plane is always the owner of the Color object. (why? well, the naive reason is that we simply
     //cannot change the owner, there are other references to that owner.)
     //There are only 2 ways to make sure of that here: by reference or creating a copy
-    pub fn color(&self, hit_point: &Vector3) -> Color {
-        match *self {
-            Element::Sphere(ref s) => {
-                s.material.coloration.color( &s.texture_coords(hit_point) )
-            },
-            Element::Plane(ref p) => {
-                p.material.coloration.color( &p.texture_coords(hit_point) )
-            }
-        }
+    pub fn color(&self, hit_point: &Vector3, footprint: Option<&Footprint>) -> Color {
+        self.material().color(&self.texture_coords(hit_point), &self.pattern_point(hit_point), footprint)
+    }
+    pub fn albedo(&self, hit_point: &Vector3) -> f32 {
+        let material = self.material();
+        material.scalar(&material.albedo, &self.texture_coords(hit_point), &self.pattern_point(hit_point))
     }
-    pub fn albedo(&self) -> f32 {
+    //Where world space procedurals are looked up: the hit point in the element's own space (and that of the geometry
+    //an instance shows), so the pattern turns and moves with the element and every instance shows the same one
+    pub fn pattern_point(&self, hit_point: &Vector3) -> Vector3 {
+        let local = match self.transform() {
+            Some(t) => t.point_to_local(hit_point),
+            None => *hit_point,
+        };
         match *self {
-            Element::Sphere(ref s) => s.material.albedo,
-            Element::Plane(ref p) => p.material.albedo,
+            Element::Instance(ref i) => i.target().pattern_point(&local),
+            _ => local,
         }
     }
     pub fn material(&self) -> &Material {
         match *self {
             Element::Sphere(ref s) => &s.material,
             Element::Plane(ref p) => &p.material,
+            Element::Box(ref b) => &b.material,
+            Element::Quad(ref q) | Element::Rectangle(ref q) => &q.material,
+            Element::Cylinder(ref c) => &c.material,
+            Element::Cone(ref c) => &c.material,
+            Element::Disk(ref d) => &d.material,
+            Element::Torus(ref t) => &t.material,
+            Element::Instance(ref i) => i.material.as_ref().unwrap_or_else(|| i.target().material()),
+            Element::Csg(ref c) => &c.material,
+            Element::Sdf(ref s) => &s.material,
+            Element::Heightfield(ref h) => &h.material,
+            Element::Patches(ref m) | Element::Subdivision(ref m) => &m.material,
+        }
+    }
+    pub fn transform(&self) -> Option<&Transform> {
+        match *self {
+            Element::Sphere(ref s) => s.transform.as_ref(),
+            Element::Plane(ref p) => p.transform.as_ref(),
+            Element::Box(ref b) => b.transform.as_ref(),
+            Element::Quad(ref q) | Element::Rectangle(ref q) => q.transform.as_ref(),
+            Element::Cylinder(ref c) => c.transform.as_ref(),
+            Element::Cone(ref c) => c.transform.as_ref(),
+            Element::Disk(ref d) => d.transform.as_ref(),
+            Element::Torus(ref t) => t.transform.as_ref(),
+            Element::Instance(ref i) => i.transform.as_ref(),
+            Element::Csg(ref c) => c.transform.as_ref(),
+            Element::Sdf(ref s) => s.transform.as_ref(),
+            Element::Heightfield(ref h) => h.transform.as_ref(),
+            Element::Patches(ref m) | Element::Subdivision(ref m) => m.transform.as_ref(),
+        }
+    }
+    //The element where it is at a time in the frame. Its surface is only ever asked about by a hit point, so
+    //something that moves is copied and stopped where the ray caught it; everything else is used as it is
+    pub fn at(&self, time: f64) -> Cow<'_, Element> {
+        if !self.is_moving() {
+            return Cow::Borrowed(self);
+        }
+        let mut stopped = self.clone();
+        stopped.stop(time);
+        Cow::Owned(stopped)
+    }
+    fn is_moving(&self) -> bool {
+        self.transform().is_some_and(Transform::is_moving) || match *self {
+            Element::Sphere(ref s) => s.end_center.is_some(),
+            Element::Instance(ref i) => i.target().is_moving(),
+            Element::Csg(ref c) => c.left.is_moving() || c.right.is_moving(),
+            _ => false,
+        }
+    }
+    fn stop(&mut self, time: f64) {
+        let transform = self.transform().map(|t| t.at(time));
+        self.set_transform(transform);
+        match *self {
+            Element::Sphere(ref mut s) => {
+                s.center = s.center_at(time);
+                s.end_center = None;
+            },
+            Element::Instance(ref mut i) if i.target().is_moving() => {
+                i.target = Some(Arc::new(i.target().at(time).into_owned()));
+            },
+            Element::Csg(ref mut c) => {
+                c.left.stop(time);
+                c.right.stop(time);
+            },
+            _ => {},
+        }
+    }
+    pub fn set_transform(&mut self, transform: Option<Transform>) {
+        let slot = match *self {
+            Element::Sphere(ref mut s) => &mut s.transform,
+            Element::Plane(ref mut p) => &mut p.transform,
+            Element::Box(ref mut b) => &mut b.transform,
+            Element::Quad(ref mut q) | Element::Rectangle(ref mut q) => &mut q.transform,
+            Element::Cylinder(ref mut c) => &mut c.transform,
+            Element::Cone(ref mut c) => &mut c.transform,
+            Element::Disk(ref mut d) => &mut d.transform,
+            Element::Torus(ref mut t) => &mut t.transform,
+            Element::Instance(ref mut i) => &mut i.transform,
+            Element::Csg(ref mut c) => &mut c.transform,
+            Element::Sdf(ref mut s) => &mut s.transform,
+            Element::Heightfield(ref mut h) => &mut h.transform,
+            Element::Patches(ref mut m) | Element::Subdivision(ref mut m) => &mut m.transform,
+        };
+        *slot = transform;
+    }
+    pub fn set_material(&mut self, material: Material) {
+        match *self {
+            Element::Sphere(ref mut s) => s.material = material,
+            Element::Plane(ref mut p) => p.material = material,
+            Element::Box(ref mut b) => b.material = material,
+            Element::Quad(ref mut q) | Element::Rectangle(ref mut q) => q.material = material,
+            Element::Cylinder(ref mut c) => c.material = material,
+            Element::Cone(ref mut c) => c.material = material,
+            Element::Disk(ref mut d) => d.material = material,
+            Element::Torus(ref mut t) => t.material = material,
+            Element::Instance(ref mut i) => i.material = Some(material),
+            Element::Csg(ref mut c) => c.material = material,
+            Element::Sdf(ref mut s) => s.material = material,
+            Element::Heightfield(ref mut h) => h.material = material,
+            Element::Patches(ref mut m) | Element::Subdivision(ref mut m) => m.material = material,
         }
     }
     pub fn surface_normal(&self, hit_point: &Vector3) -> Vector3 {
+        match self.transform() {
+            Some(t) => t.normal_to_world(&self.local_surface_normal(&t.point_to_local(hit_point))),
+            None => self.local_surface_normal(hit_point),
+        }
+    }
+    fn local_surface_normal(&self, hit_point: &Vector3) -> Vector3 {
         match *self {
             Element::Sphere(ref s) => s.surface_normal(hit_point),
             Element::Plane(ref p) => p.surface_normal(hit_point),
+            Element::Box(ref b) => b.surface_normal(hit_point),
+            Element::Quad(ref q) | Element::Rectangle(ref q) => q.surface_normal(hit_point),
+            Element::Cylinder(ref c) => c.surface_normal(hit_point),
+            Element::Cone(ref c) => c.surface_normal(hit_point),
+            Element::Disk(ref d) => d.surface_normal(hit_point),
+            Element::Torus(ref t) => t.surface_normal(hit_point),
+            Element::Instance(ref i) => i.target().surface_normal(hit_point),
+            Element::Csg(ref c) => c.surface_normal(hit_point),
+            Element::Sdf(ref s) => s.surface_normal(hit_point),
+            Element::Heightfield(ref h) => h.surface_normal(hit_point),
+            Element::Patches(ref m) | Element::Subdivision(ref m) => m.surface_normal(hit_point),
         }
     }
+    pub fn tangents(&self, hit_point: &Vector3) -> (Vector3, Vector3) {
+        match self.transform() {
+            Some(t) => {
+                let (dpdu, dpdv) = self.local_tangents(&t.point_to_local(hit_point));
+                (t.direction_to_world(&dpdu), t.direction_to_world(&dpdv))
+            },
+            None => self.local_tangents(hit_point),
+        }
+    }
+    fn local_tangents(&self, hit_point: &Vector3) -> (Vector3, Vector3) {
+        match *self {
+            Element::Sphere(ref s) => s.tangents(hit_point),
+            Element::Plane(ref p) => p.tangents(hit_point),
+            Element::Box(ref b) => b.tangents(hit_point),
+            Element::Quad(ref q) | Element::Rectangle(ref q) => q.tangents(hit_point),
+            Element::Cylinder(ref c) => c.tangents(hit_point),
+            Element::Cone(ref c) => c.tangents(hit_point),
+            Element::Disk(ref d) => d.tangents(hit_point),
+            Element::Torus(ref t) => t.tangents(hit_point),
+            Element::Instance(ref i) => i.target().tangents(hit_point),
+            Element::Csg(ref c) => c.tangents(hit_point),
+            Element::Sdf(ref s) => s.tangents(hit_point),
+            Element::Heightfield(ref h) => h.tangents(hit_point),
+            Element::Patches(ref m) | Element::Subdivision(ref m) => m.tangents(hit_point),
+        }
+    }
+    //The normal used for lighting: the geometric normal bent by the material's normal or bump map, if it has one
+    pub fn shading_normal(&self, hit_point: &Vector3, surface_normal: &Vector3) -> Vector3 {
+        let material = self.material();
+        if material.normal_map.is_none() && material.bump_map.is_none() {
+            return *surface_normal;
+        }
+        let (dpdu, dpdv) = self.tangents(hit_point);
+        material.shading_normal(surface_normal, dpdu, dpdv, &self.texture_coords(hit_point), hit_point, |p| self.pattern_point(p))
+    }
     pub fn texture_coords(&self, hit_point: &Vector3) -> TextureCoords {
+        match self.transform() {
+            Some(t) => self.local_texture_coords(&t.point_to_local(hit_point)),
+            None => self.local_texture_coords(hit_point),
+        }
+    }
+    fn local_texture_coords(&self, hit_point: &Vector3) -> TextureCoords {
         match *self {
             Element::Sphere(ref s) => s.texture_coords(hit_point),
             Element::Plane(ref p) => p.texture_coords(hit_point),
+            Element::Box(ref b) => b.texture_coords(hit_point),
+            Element::Quad(ref q) | Element::Rectangle(ref q) => q.texture_coords(hit_point),
+            Element::Cylinder(ref c) => c.texture_coords(hit_point),
+            Element::Cone(ref c) => c.texture_coords(hit_point),
+            Element::Disk(ref d) => d.texture_coords(hit_point),
+            Element::Torus(ref t) => t.texture_coords(hit_point),
+            Element::Instance(ref i) => i.target().texture_coords(hit_point),
+            Element::Csg(ref c) => c.texture_coords(hit_point),
+            Element::Sdf(ref s) => s.texture_coords(hit_point),
+            Element::Heightfield(ref h) => h.texture_coords(hit_point),
+            Element::Patches(ref m) | Element::Subdivision(ref m) => m.texture_coords(hit_point),
+        }
+    }
+    //Projects the neighbouring pixels' rays onto the tangent plane at the hit and measures how far apart they land in texture space.
+    //The texture coordinates are differenced over a small step instead of between the far apart points so that seams
+    //in a parameterisation (e.g. where a sphere's u wraps from 1 to 0) do not read as a huge footprint.
+    pub fn footprint(&self, hit_point: &Vector3, surface_normal: &Vector3, differential: &Differential) -> Option<Footprint> {
+        let offset = |origin: Vector3, direction: Vector3| -> Option<Vector3> {
+            let denom = surface_normal.dot(&direction);
+            if denom.abs() < 1e-9 {
+                return None;
+            }
+            let t = surface_normal.dot(&(*hit_point - origin)) / denom;
+            Some(origin + direction * t - *hit_point)
+        };
+        let dpdx = offset(differential.rx_origin, differential.rx_direction)?;
+        let dpdy = offset(differential.ry_origin, differential.ry_direction)?;
+
+        const STEP: f64 = 1e-3;
+        let base = self.texture_coords(hit_point);
+        let along_x = self.texture_coords(&(*hit_point + dpdx * STEP));
+        let along_y = self.texture_coords(&(*hit_point + dpdy * STEP));
+        Some(Footprint {
+            dudx: (along_x.x - base.x) / STEP as f32,
+            dvdx: (along_x.y - base.y) / STEP as f32,
+            dudy: (along_y.x - base.x) / STEP as f32,
+            dvdy: (along_y.y - base.y) / STEP as f32,
+        })
+    }
+    //Whether the element encloses a volume, so it can tell what is inside it (see Intersectable::intervals)
+    pub fn is_solid(&self) -> bool {
+        matches!(*self, Element::Sphere(_) | Element::Box(_) | Element::Cylinder(_) | Element::Cone(_) | Element::Torus(_) | Element::Csg(_))
+    }
+
+    //Flat elements seen from both sides, whose normal is turned towards the ray when they are shaded
+    pub fn is_two_sided(&self) -> bool {
+        match *self {
+            Element::Quad(_) | Element::Rectangle(_) | Element::Disk(_) => true,
+            Element::Instance(ref i) => i.target().is_two_sided(),
+            _ => false,
         }
     }
+
     pub fn obj_str(&self) -> &str {
         match *self {
             Element::Sphere(ref s) => "Sphere",
             Element::Plane(ref p) => "Plane",
+            Element::Box(_) => "Box",
+            Element::Quad(_) => "Quad",
+            Element::Rectangle(_) => "Rectangle",
+            Element::Cylinder(_) => "Cylinder",
+            Element::Cone(_) => "Cone",
+            Element::Disk(_) => "Disk",
+            Element::Torus(_) => "Torus",
+            Element::Instance(_) => "Instance",
+            Element::Csg(_) => "Csg",
+            Element::Sdf(_) => "Sdf",
+            Element::Heightfield(_) => "Heightfield",
+            Element::Patches(_) => "Patches",
+            Element::Subdivision(_) => "Subdivision",
         }
     }
     
@@ -66,13 +329,98 @@ impl Element {
 
 pub trait Intersectable {
     fn intersect(&self, ray: &Ray) -> Option<f64>;
+
+    //The stretches of the ray's whole line (behind its origin too) that are inside the shape, as sorted
+    //(entry, exit) distances. Only closed solids have an inside; surfaces that enclose nothing give None.
+    fn intervals(&self, _ray: &Ray) -> Option<Vec<(f64, f64)>> {
+        None
+    }
 }
 
+//Transformed elements are intersected in their own space, where they have the shape they are written with
 impl Intersectable for Element {
     fn intersect(&self, ray: &Ray) -> Option<f64> {
-        match *self {
+        self.hit(ray).map(|(distance, _)| distance)
+    }
+
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(f64, f64)>> {
+        let to_world = |intervals: Vec<(f64, f64)>, stretch: f64| {
+            intervals.into_iter().map(|(entry, exit)| (entry / stretch, exit / stretch)).collect()
+        };
+        match self.transform() {
+            Some(t) if t.is_moving() => {
+                let (local, stretch) = t.at(ray.time).ray_to_local(ray);
+                self.local_intervals(&local).map(|i| to_world(i, stretch))
+            },
+            Some(t) => {
+                let (local, stretch) = t.ray_to_local(ray);
+                self.local_intervals(&local).map(|i| to_world(i, stretch))
+            },
+            None => self.local_intervals(ray),
+        }
+    }
+}
+
+impl Element {
+    //The distance to the nearest hit and, for meshes, the triangle it is on (see on_triangle)
+    pub fn hit(&self, ray: &Ray) -> Option<(f64, Option<usize>)> {
+        match self.transform() {
+            Some(t) if t.is_moving() => {
+                let (local, stretch) = t.at(ray.time).ray_to_local(ray);
+                self.local_hit(&local).map(|(distance, triangle)| (distance / stretch, triangle))
+            },
+            Some(t) => {
+                let (local, stretch) = t.ray_to_local(ray);
+                self.local_hit(&local).map(|(distance, triangle)| (distance / stretch, triangle))
+            },
+            None => self.local_hit(ray),
+        }
+    }
+
+    fn local_hit(&self, ray: &Ray) -> Option<(f64, Option<usize>)> {
+        let distance = match *self {
             Element::Sphere(ref s) => s.intersect(ray),
             Element::Plane(ref p) => p.intersect(ray),
+            Element::Box(ref b) => b.intersect(ray),
+            Element::Quad(ref q) | Element::Rectangle(ref q) => q.intersect(ray),
+            Element::Cylinder(ref c) => c.intersect(ray),
+            Element::Cone(ref c) => c.intersect(ray),
+            Element::Disk(ref d) => d.intersect(ray),
+            Element::Torus(ref t) => t.intersect(ray),
+            Element::Instance(ref i) => return i.target().hit(ray),
+            Element::Csg(ref c) => c.intersect(ray),
+            Element::Sdf(ref s) => s.intersect(ray),
+            Element::Heightfield(ref h) => h.intersect(ray),
+            Element::Patches(ref m) | Element::Subdivision(ref m) => return m.hit(ray).map(|(distance, triangle)| (distance, Some(triangle))),
+        };
+        distance.map(|d| (d, None))
+    }
+
+    //A copy of a mesh (or an instance of one) that knows which of its triangles was hit, so shading the hit does
+    //not have to look for it again
+    pub fn on_triangle(&self, triangle: usize) -> Element {
+        match *self {
+            Element::Patches(ref m) => Element::Patches(m.on_triangle(triangle)),
+            Element::Subdivision(ref m) => Element::Subdivision(m.on_triangle(triangle)),
+            Element::Instance(ref i) => {
+                let mut pinned = i.clone();
+                pinned.target = Some(Arc::new(i.target().on_triangle(triangle)));
+                Element::Instance(pinned)
+            },
+            ref other => other.clone(),
+        }
+    }
+
+    fn local_intervals(&self, ray: &Ray) -> Option<Vec<(f64, f64)>> {
+        match *self {
+            Element::Sphere(ref s) => s.intervals(ray),
+            Element::Box(ref b) => b.intervals(ray),
+            Element::Cylinder(ref c) => c.intervals(ray),
+            Element::Cone(ref c) => c.intervals(ray),
+            Element::Torus(ref t) => t.intervals(ray),
+            Element::Instance(ref i) => i.target().intervals(ray),
+            Element::Csg(ref c) => c.intervals(ray),
+            _ => None,
         }
     }
 }
@@ -83,38 +431,197 @@ pub struct Scene {
     pub width: u32,
     pub height: u32,
     pub fov: f64,
+    #[serde(deserialize_with = "group::elements")]
     pub elements: Vec<Element>,
     pub lights: Vec<Light>,
     pub shadow_bias: f64,
     pub max_recursion_depth: u32,
+    #[serde(default)]
+    pub color_space: ColorSpace, //the space the rendered image is encoded in; sRGB unless asked otherwise
+    #[serde(default, deserialize_with = "spectral_samples")]
+    pub spectral_samples: u32, //wavelengths traced per pixel, 0 renders in plain RGB, at most MAX_SPECTRAL_SAMPLES
+    #[serde(default)]
+    pub fog: Option<Fog>,
+    #[serde(default = "one_sample", deserialize_with = "samples")]
+    pub samples: u32, //rays traced per pixel and averaged, each at its own time while the shutter is open, at most MAX_SAMPLES
+    #[serde(default)]
+    pub camera: Camera,
+    #[serde(default, deserialize_with = "group::geometry")]
+    pub geometry: HashMap<String, Vec<Arc<Element>>>, //shared by the Instance elements that name it
+}
+
+fn one_sample() -> u32 {
+    1
+}
+
+const MAX_SAMPLES: u32 = 1024;
+
+fn samples<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match u32::deserialize(deserializer)? {
+        samples if samples <= MAX_SAMPLES => Ok(samples),
+        samples => Err(D::Error::custom(format!("at most {} samples per pixel are allowed, not {}", MAX_SAMPLES, samples))),
+    }
+}
+
+//Every sample of a pixel is traced once per wavelength
+const MAX_SPECTRAL_SAMPLES: u32 = 64;
+
+fn spectral_samples<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match u32::deserialize(deserializer)? {
+        samples if samples <= MAX_SPECTRAL_SAMPLES => Ok(samples),
+        samples => Err(D::Error::custom(format!("at most {} spectral samples are allowed, not {}", MAX_SPECTRAL_SAMPLES, samples))),
+    }
 }
 
+const MAX_CUTOUT_SKIPS: u32 = 16;
+const CUTOUT_OFFSET: f64 = 1e-6;
+const MAX_SHADOW_LAYERS: u32 = 16;
+
 impl Scene {
+    //Points each instance at the geometry it names, one instance per element when that geometry is a group.
+    //Called once after the scene is read, since serde has no way to look at the rest of the document while
+    //deserializing one element
+    pub fn resolve_instances(&mut self) -> Result<(), String> {
+        let geometry = &self.geometry;
+        if geometry.values().flatten().any(|g| matches!(**g, Element::Instance(_))) {
+            return Err(String::from("geometry cannot itself contain instances"));
+        }
+        let mut elements = Vec::with_capacity(self.elements.len());
+        for element in self.elements.drain(..) {
+            match element {
+                Element::Instance(instance) => {
+                    let targets = geometry.get(&instance.geometry).ok_or_else(|| format!("unknown geometry: {}", instance.geometry))?;
+                    for target in targets {
+                        let mut part = instance.clone();
+                        part.target = Some(target.clone());
+                        elements.push(Element::Instance(part));
+                    }
+                },
+                element => elements.push(element),
+            }
+        }
+        self.elements = elements;
+        Ok(())
+    }
+
     pub fn trace(&self, ray: &Ray) -> Option<Intersection> {
         self.elements
             .iter()
-            .filter_map(|s| s.intersect(ray).map(|d| Intersection::new(d, s)))
-            .min_by(|i1, i2| i1.distance.partial_cmp(&i2.distance).unwrap())
+            .filter_map(|s| Scene::intersect_opaque(s, ray).map(|(d, triangle)| (d, s, triangle)))
+            .min_by(|(d1, _, _), (d2, _, _)| d1.partial_cmp(d2).unwrap())
+            .map(|(d, s, triangle)| Intersection::new(d, s, ray.time, triangle)) //only the nearest is caught where it had moved to
+    }
+
+    //How much of a light's color reaches the origin of a shadow ray. Opaque elements block it completely, refractive
+    //ones let their transparency through, tinted by their surface color and by absorption over the distance travelled inside.
+    //Fog and volumes dim it by the distance it travels through them
+    pub fn light_transmittance(&self, shadow_ray: &Ray, light_distance: f64, wavelength: Option<f32>) -> Color {
+        let mut transmittance = match self.fog {
+            Some(ref fog) if light_distance.is_finite() => fog.medium.transmittance(light_distance, wavelength), //directional lights shine in from above the fog
+            _ => Color { red: 1.0, green: 1.0, blue: 1.0 },
+        };
+        let mut ray = Ray { origin: shadow_ray.origin, direction: shadow_ray.direction, differential: None, time: shadow_ray.time };
+        let mut travelled = 0.0;
+        for _ in 0..MAX_SHADOW_LAYERS {
+            let intersection = match self.trace(&ray) {
+                Some(i) if travelled + i.distance < light_distance => i,
+                _ => return transmittance,
+            };
+            let hit_point = ray.origin + ray.direction * intersection.distance;
+            let element = intersection.element;
+            let material = element.material();
+            match material.surface {
+                SurfaceType::Refractive { ref transparency, absorption, .. } => {
+                    let texture_coords = element.texture_coords(&hit_point);
+                    let pattern_point = element.pattern_point(&hit_point);
+                    let transparency = material.scalar(transparency, &texture_coords, &pattern_point);
+                    transmittance = transmittance * spectrum::evaluate(material.color(&texture_coords, &pattern_point, None), wavelength) * transparency;
+                    if let Some(absorption) = absorption {
+                        if ray.direction.dot(&element.surface_normal(&hit_point)) > 0.0 { //leaving the element
+                            transmittance = transmittance * spectrum::evaluate(absorption, wavelength).transmittance(intersection.distance as f32);
+                        }
+                    }
+                },
+                SurfaceType::Volume { ref medium } => {
+                    if ray.direction.dot(&element.surface_normal(&hit_point)) > 0.0 {
+                        transmittance = transmittance * medium.transmittance(intersection.distance, wavelength);
+                    }
+                },
+                _ => return Color { red: 0.0, green: 0.0, blue: 0.0 },
+            }
+            travelled += intersection.distance + self.shadow_bias;
+            ray = Ray {
+                origin: hit_point + ray.direction * self.shadow_bias,
+                direction: ray.direction,
+                differential: None,
+                time: ray.time,
+            };
+        }
+        Color { red: 0.0, green: 0.0, blue: 0.0 } //too many layers to see through
+    }
+
+    //Like intersect, but keeps going through the element where its material's alpha cuts a hole in it
+    fn intersect_opaque(element: &Element, ray: &Ray) -> Option<(f64, Option<usize>)> {
+        let (mut distance, mut triangle) = element.hit(ray)?;
+        if !element.material().has_cutout() {
+            return Some((distance, triangle));
+        }
+        let element = element.at(ray.time);
+        for _ in 0..MAX_CUTOUT_SKIPS {
+            let hit_point = ray.origin + ray.direction * distance;
+            if element.material().is_opaque_at(&element.texture_coords(&hit_point)) {
+                return Some((distance, triangle));
+            }
+            let next = Ray {
+                origin: hit_point + ray.direction * CUTOUT_OFFSET,
+                direction: ray.direction,
+                differential: None,
+                time: ray.time,
+            };
+            let (further, next_triangle) = element.hit(&next)?;
+            distance += CUTOUT_OFFSET + further;
+            triangle = next_triangle;
+        }
+        None
     }
 }
 
 
 pub struct Intersection<'a> {
     pub distance: f64,
-    pub element: &'a Element,
+    pub element: Cow<'a, Element>, //as it was when the ray hit it
+    pub time: f64, //of the ray that hit it
+    source: &'a Element,
     //Prevent outside code from constructing this; should use the new method and check the distance.
     _secret: (),
 }
 
 impl<'a> Intersection<'a> {
-    pub fn new<'b>(distance: f64, element: &'b Element) -> Intersection<'b> {
+    pub fn new<'b>(distance: f64, source: &'b Element, time: f64, triangle: Option<usize>) -> Intersection<'b> {
         if !distance.is_finite() {
             panic!("Intersection must have a finite distance.");
         }
+        let element = match triangle {
+            Some(triangle) => Cow::Owned(source.at(time).on_triangle(triangle)),
+            None => source.at(time),
+        };
         Intersection {
-            distance: distance,
-            element: element,
+            distance,
+            element,
+            time,
+            source,
             _secret: (),
         }
     }
-}
\ No newline at end of file
+
+    //Whether both hits are on the same element of the scene, even if it had moved between them
+    pub fn same_element(&self, other: &Intersection) -> bool {
+        std::ptr::eq(self.source, other.source)
+    }
+}
@@ -0,0 +1,281 @@
+use crate::vector3::Vector3;
+use crate::ray::Ray;
+use crate::scene::Intersectable;
+use crate::material::{Material, TextureCoords};
+use crate::transform::Transform;
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+const HIT_DISTANCE: f64 = 1e-4;
+const GRADIENT_STEP: f64 = 1e-4;
+
+//Every step and every Mandelbulb iteration is paid for at each point of every ray that comes near the shape
+const MAX_STEPS: u32 = 4096;
+const MAX_ITERATIONS: u32 = 64;
+
+fn default_max_steps() -> u32 {
+    256
+}
+
+fn default_max_distance() -> f64 {
+    1000.0
+}
+
+//A shape given by its signed distance function (negative inside) and rendered by sphere tracing: stepping along
+//the ray by the distance to the nearest surface, which can never overshoot it, until that distance is tiny.
+//e.g. { "Sdf": { "shape": { "SmoothUnion": { "k": 0.3, "shapes": [ { "Sphere": { "radius": 1 } }, ... ] } }, "material": ... } }
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "DistanceFieldDesc")]
+pub struct DistanceField {
+    pub shape: Sdf,
+    pub material: Material,
+    pub transform: Option<Transform>,
+    pub max_steps: u32, //at most MAX_STEPS
+    pub max_distance: f64, //rays that get this far without hitting anything miss
+}
+
+#[derive(Deserialize)]
+struct DistanceFieldDesc {
+    shape: Sdf,
+    material: Material,
+    #[serde(default)]
+    transform: Option<Transform>,
+    #[serde(default = "default_max_steps")]
+    max_steps: u32,
+    #[serde(default = "default_max_distance")]
+    max_distance: f64,
+}
+
+impl TryFrom<DistanceFieldDesc> for DistanceField {
+    type Error = String;
+
+    fn try_from(desc: DistanceFieldDesc) -> Result<Self, Self::Error> {
+        if desc.max_steps > MAX_STEPS {
+            return Err(format!("a distance field takes at most {} steps, not {}", MAX_STEPS, desc.max_steps));
+        }
+        if desc.max_distance.is_nan() || desc.max_distance <= 0.0 {
+            return Err(String::from("a distance field max_distance must be positive"));
+        }
+        desc.shape.check()?;
+        Ok(DistanceField {
+            shape: desc.shape,
+            material: desc.material,
+            transform: desc.transform,
+            max_steps: desc.max_steps,
+            max_distance: desc.max_distance,
+        })
+    }
+}
+
+//Primitives sit at the origin; Translate, Scale and the element's transform move them about
+#[derive(Clone, Debug, Deserialize)]
+pub enum Sdf {
+    Sphere { radius: f64 },
+    Box { half_size: Vector3 },
+    Torus { major_radius: f64, minor_radius: f64 },
+    Cylinder { radius: f64, half_height: f64 },
+    Mandelbulb { #[serde(default = "default_power")] power: f64, #[serde(default = "default_iterations")] iterations: u32 },
+    Union { shapes: Vec<Sdf> },
+    Intersection { shapes: Vec<Sdf> },
+    Difference { base: Box<Sdf>, subtract: Box<Sdf> },
+    SmoothUnion { shapes: Vec<Sdf>, k: f64 }, //k is roughly how far apart shapes start to melt together
+    Round { radius: f64, shape: Box<Sdf> }, //grows the shape by radius, rounding its edges
+    Translate { offset: Vector3, shape: Box<Sdf> },
+    Scale { factor: f64, shape: Box<Sdf> },
+    Repeat { period: Vector3, shape: Box<Sdf> }, //endless copies every period along each axis; 0 leaves an axis alone
+    Twist { rate: f64, shape: Box<Sdf> }, //radians of turn about y per unit of height
+}
+
+fn default_power() -> f64 {
+    8.0
+}
+
+fn default_iterations() -> u32 {
+    8
+}
+
+impl Sdf {
+    //Rejects shapes that would be endlessly expensive to evaluate or whose distance would not be a distance
+    fn check(&self) -> Result<(), String> {
+        match *self {
+            Sdf::Mandelbulb { iterations, .. } if iterations > MAX_ITERATIONS => {
+                Err(format!("a Mandelbulb takes at most {} iterations, not {}", MAX_ITERATIONS, iterations))
+            },
+            Sdf::Scale { factor, .. } if factor.is_nan() || factor <= 0.0 => Err(format!("a Scale factor must be positive, not {}", factor)),
+            Sdf::Union { ref shapes } | Sdf::Intersection { ref shapes } | Sdf::SmoothUnion { ref shapes, .. } => {
+                shapes.iter().try_for_each(Sdf::check)
+            },
+            Sdf::Difference { ref base, ref subtract } => base.check().and_then(|_| subtract.check()),
+            Sdf::Round { ref shape, .. } | Sdf::Translate { ref shape, .. } | Sdf::Scale { ref shape, .. }
+                | Sdf::Repeat { ref shape, .. } | Sdf::Twist { ref shape, .. } => shape.check(),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn distance(&self, p: &Vector3) -> f64 {
+        match *self {
+            Sdf::Sphere { radius } => p.length() - radius,
+            Sdf::Box { half_size } => {
+                let q = Vector3::new(p.x.abs() - half_size.x, p.y.abs() - half_size.y, p.z.abs() - half_size.z);
+                let outside = Vector3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).length();
+                outside + q.x.max(q.y).max(q.z).min(0.0)
+            },
+            Sdf::Torus { major_radius, minor_radius } => {
+                let ring = (p.x * p.x + p.z * p.z).sqrt() - major_radius;
+                (ring * ring + p.y * p.y).sqrt() - minor_radius
+            },
+            Sdf::Cylinder { radius, half_height } => {
+                let (dr, dy) = ((p.x * p.x + p.z * p.z).sqrt() - radius, p.y.abs() - half_height);
+                dr.max(dy).min(0.0) + (dr.max(0.0).powi(2) + dy.max(0.0).powi(2)).sqrt()
+            },
+            Sdf::Mandelbulb { power, iterations } => mandelbulb(p, power, iterations),
+            Sdf::Union { ref shapes } => shapes.iter().map(|s| s.distance(p)).fold(f64::INFINITY, f64::min),
+            Sdf::Intersection { ref shapes } => shapes.iter().map(|s| s.distance(p)).fold(f64::NEG_INFINITY, f64::max),
+            Sdf::Difference { ref base, ref subtract } => base.distance(p).max(-subtract.distance(p)),
+            Sdf::SmoothUnion { ref shapes, k } => {
+                shapes.iter().map(|s| s.distance(p)).fold(None, |blended: Option<f64>, d| Some(match blended {
+                    None => d,
+                    Some(b) => smooth_min(b, d, k),
+                })).unwrap_or(f64::INFINITY)
+            },
+            Sdf::Round { radius, ref shape } => shape.distance(p) - radius,
+            Sdf::Translate { offset, ref shape } => shape.distance(&(*p - offset)),
+            Sdf::Scale { factor, ref shape } => shape.distance(&(*p * (1.0 / factor))) * factor,
+            Sdf::Repeat { period, ref shape } => {
+                let wrap = |v: f64, period: f64| if period > 0.0 { v - period * (v / period).round() } else { v };
+                shape.distance(&Vector3::new(wrap(p.x, period.x), wrap(p.y, period.y), wrap(p.z, period.z)))
+            },
+            Sdf::Twist { rate, ref shape } => {
+                let (sin, cos) = (rate * p.y).sin_cos();
+                let twisted = Vector3::new(cos * p.x - sin * p.z, p.y, sin * p.x + cos * p.z);
+                //twisting stretches space, so the distance is no longer a safe step; halving it keeps the march from overshooting
+                shape.distance(&twisted) * 0.5
+            },
+        }
+    }
+}
+
+//Polynomial smooth minimum (Quilez): like min, but blends the two over a band of width k
+fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b * (1.0 - h) + a * h - k * h * (1.0 - h)
+}
+
+//Distance estimate for the power n Mandelbulb from the running derivative of the iteration
+fn mandelbulb(p: &Vector3, power: f64, iterations: u32) -> f64 {
+    let mut z = *p;
+    let mut dr = 1.0;
+    let mut r = 0.0;
+    for _ in 0..iterations {
+        r = z.length();
+        if r > 2.0 {
+            break;
+        }
+        let theta = (z.z / r).acos() * power;
+        let phi = z.y.atan2(z.x) * power;
+        dr = r.powf(power - 1.0) * power * dr + 1.0;
+        let zr = r.powf(power);
+        z = Vector3::new(theta.sin() * phi.cos(), phi.sin() * theta.sin(), theta.cos()) * zr + *p;
+    }
+    0.5 * r.ln() * r / dr
+}
+
+impl DistanceField {
+    pub fn surface_normal(&self, hit_point: &Vector3) -> Vector3 { //the gradient of the distance, by central differences
+        let h = GRADIENT_STEP;
+        let d = |x: f64, y: f64, z: f64| self.shape.distance(&(*hit_point + Vector3::new(x, y, z)));
+        Vector3::new(
+            d(h, 0.0, 0.0) - d(-h, 0.0, 0.0),
+            d(0.0, h, 0.0) - d(0.0, -h, 0.0),
+            d(0.0, 0.0, h) - d(0.0, 0.0, -h),
+        ).normalize()
+    }
+
+    //Projected from the origin like a sphere's, which suits the mostly round shapes that distance fields are used for
+    pub fn texture_coords(&self, hit_point: &Vector3) -> TextureCoords {
+        let length = hit_point.length().max(1e-12);
+        TextureCoords {
+            x: (1.0 + (hit_point.z.atan2(hit_point.x) as f32) / std::f32::consts::PI) * 0.5,
+            y: (hit_point.y / length).clamp(-1.0, 1.0).acos() as f32 / std::f32::consts::PI,
+        }
+    }
+
+    pub fn tangents(&self, hit_point: &Vector3) -> (Vector3, Vector3) {
+        let normal = self.surface_normal(hit_point);
+        let around = Vector3::new(-hit_point.z, 0.0, hit_point.x);
+        let dpdu = if around.length() < 1e-9 { Vector3::new(0.0, 0.0, 1.0) } else { around.normalize() };
+        (dpdu, normal.cross(&dpdu))
+    }
+}
+
+impl Intersectable for DistanceField {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let length = ray.direction.length();
+        let direction = ray.direction * (1.0 / length);
+        let mut t = 0.0;
+        let mut left_surface = false; //rays that start on the surface (reflections, shadows) must get clear of it first
+        for _ in 0..self.max_steps {
+            let distance = self.shape.distance(&(ray.origin + direction * t)).abs();
+            if distance < HIT_DISTANCE {
+                if left_surface {
+                    return Some(t / length);
+                }
+            } else {
+                left_surface = true;
+            }
+            t += distance.max(HIT_DISTANCE);
+            if t > self.max_distance {
+                return None;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(shape: &str, extra: &str) -> Result<DistanceField, serde_json::Error> {
+        serde_json::from_str(&format!(r#"{{ "shape": {}, "material": {{ "coloration": {{ "Color": {{ "red": 1, "green": 1, "blue": 1 }} }}, "albedo": 0.5, "surface": "Diffuse" }}{} }}"#, shape, extra))
+    }
+
+    fn ray(origin: Vector3, direction: Vector3) -> Ray {
+        Ray { origin, direction, differential: None, time: 0.0 }
+    }
+
+    #[test]
+    fn sphere_and_box_distances() {
+        let sphere = Sdf::Sphere { radius: 1.0 };
+        assert!((sphere.distance(&Vector3::new(3.0, 0.0, 0.0)) - 2.0).abs() < 1e-12);
+        assert!((sphere.distance(&Vector3::zero()) + 1.0).abs() < 1e-12);
+        let cube = Sdf::Box { half_size: Vector3::new(1.0, 1.0, 1.0) };
+        assert!((cube.distance(&Vector3::new(3.0, 0.0, 0.0)) - 2.0).abs() < 1e-12);
+        assert!((cube.distance(&Vector3::new(2.0, 2.0, 1.0)) - 2f64.sqrt()).abs() < 1e-12); //nearest to an edge
+        assert!((cube.distance(&Vector3::new(0.5, 0.0, 0.0)) + 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn march_hits_and_misses() {
+        let sphere = field(r#"{ "Sphere": { "radius": 1 } }"#, "").unwrap();
+        let hit = sphere.intersect(&ray(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0))).unwrap();
+        assert!((hit - 4.0).abs() < 1e-3);
+        assert!(sphere.intersect(&ray(Vector3::new(0.0, 2.0, 5.0), Vector3::new(0.0, 0.0, -1.0))).is_none());
+        assert!(sphere.intersect(&ray(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, 1.0))).is_none());
+        //one leaving the surface, like a shadow ray, does not hit it again where it starts
+        let leaving = sphere.intersect(&ray(Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 0.0, -1.0))).unwrap();
+        assert!((leaving - 2.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn expensive_or_broken_shapes_are_rejected() {
+        assert!(field(r#"{ "Sphere": { "radius": 1 } }"#, r#", "max_steps": 100000"#).is_err());
+        assert!(field(r#"{ "Mandelbulb": { "iterations": 1000000 } }"#, "").is_err());
+        assert!(field(r#"{ "Union": { "shapes": [ { "Scale": { "factor": 0, "shape": { "Sphere": { "radius": 1 } } } } ] } }"#, "").is_err());
+        assert!(field(r#"{ "Scale": { "factor": -2, "shape": { "Sphere": { "radius": 1 } } } }"#, "").is_err());
+        assert!(field(r#"{ "Scale": { "factor": 2, "shape": { "Mandelbulb": {} } } }"#, "").is_ok());
+    }
+}
@@ -0,0 +1,114 @@
+use crate::color::Color;
+use serde::Deserialize;
+
+//The visible range that spectral renders sample, in nanometres
+const MIN_WAVELENGTH: f32 = 380.0;
+const MAX_WAVELENGTH: f32 = 730.0;
+
+//A wavelength dependent index of refraction for dispersive materials. Wavelengths are in micrometres here
+//because that is how both equations are tabulated e.g. BK7 glass is { "Sellmeier": { "b": [1.0396, 0.2318, 1.0105], "c": [0.0060, 0.0200, 103.56] } }
+#[derive(Clone, Debug, Deserialize)]
+pub enum Dispersion {
+    Cauchy { a: f32, b: f32 },
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    pub fn index(&self, wavelength: f32) -> f32 {
+        let l = wavelength / 1000.0;
+        let l2 = l * l;
+        match *self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let n2 = 1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f32>();
+                n2.max(1.0).sqrt()
+            },
+        }
+    }
+}
+
+//Evenly spaced wavelengths across the visible range, each paired with the weight its radiance gets in the final
+//linear sRGB pixel. The scene is traced once per wavelength with every color in it turned into its value at that
+//wavelength (see evaluate); the weights are the CIE 1931 matching functions taken to sRGB and normalised so that a
+//flat spectrum comes out white. A channel none of the wavelengths reach (too few of them) is left black.
+pub fn samples(count: u32) -> Vec<(f32, Color)> {
+    let step = (MAX_WAVELENGTH - MIN_WAVELENGTH) / count as f32;
+    let mut samples: Vec<(f32, Color)> = (0..count)
+        .map(|i| {
+            let wavelength = MIN_WAVELENGTH + (i as f32 + 0.5) * step;
+            (wavelength, xyz_to_rgb(cie_xyz(wavelength)))
+        })
+        .collect();
+    let total = samples.iter().fold(Color { red: 0.0, green: 0.0, blue: 0.0 }, |sum, &(_, w)| sum + w);
+    let normalise = |weight: f32, total: f32| if total > 0.0 { weight / total } else { 0.0 };
+    for sample in samples.iter_mut() {
+        sample.1 = Color {
+            red: normalise(sample.1.red, total.red),
+            green: normalise(sample.1.green, total.green),
+            blue: normalise(sample.1.blue, total.blue),
+        };
+    }
+    samples
+}
+
+//A color as seen at one wavelength: with a wavelength, the value at it of a smooth spectrum with that RGB color,
+//in all three channels so that colors multiply as spectra do; without one (a plain RGB render), the color itself
+pub fn evaluate(color: Color, wavelength: Option<f32>) -> Color {
+    match wavelength {
+        Some(wavelength) => {
+            let value = rgb_to_spectrum(color, wavelength);
+            Color { red: value, green: value, blue: value }
+        },
+        None => color,
+    }
+}
+
+//Smits' "An RGB to Spectrum Conversion for Reflectances": white plus the most of cyan, magenta or yellow that the
+//color has in common, plus whatever of red, green or blue is left, using spectra chosen to be as smooth as possible.
+//Each is tabulated in ten bands from 380 to 720nm and interpolated between their centres.
+fn rgb_to_spectrum(color: Color, wavelength: f32) -> f32 {
+    let (r, g, b) = (color.red, color.green, color.blue);
+    let at = |spectrum: &[f32; 10]| {
+        let band = ((wavelength - SMITS_START) / SMITS_BAND - 0.5).clamp(0.0, 9.0);
+        let (low, fraction) = (band.floor() as usize, band.fract());
+        spectrum[low] * (1.0 - fraction) + spectrum[(low + 1).min(9)] * fraction
+    };
+    if r <= g && r <= b {
+        r * at(&WHITE) + if g <= b { (g - r) * at(&CYAN) + (b - g) * at(&BLUE) } else { (b - r) * at(&CYAN) + (g - b) * at(&GREEN) }
+    } else if g <= r && g <= b {
+        g * at(&WHITE) + if r <= b { (r - g) * at(&MAGENTA) + (b - r) * at(&BLUE) } else { (b - g) * at(&MAGENTA) + (r - b) * at(&RED) }
+    } else {
+        b * at(&WHITE) + if r <= g { (r - b) * at(&YELLOW) + (g - r) * at(&GREEN) } else { (g - b) * at(&YELLOW) + (r - g) * at(&RED) }
+    }
+}
+
+const SMITS_START: f32 = 380.0;
+const SMITS_BAND: f32 = 34.0;
+const WHITE: [f32; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const CYAN: [f32; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const MAGENTA: [f32; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const YELLOW: [f32; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const RED: [f32; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const GREEN: [f32; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const BLUE: [f32; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+//Wyman, Sloan and Shirley's multi-lobe gaussian fit of the CIE 1931 2 degree observer
+fn cie_xyz(wavelength: f32) -> (f32, f32, f32) {
+    let g = |mu: f32, below: f32, above: f32| {
+        let t = (wavelength - mu) / if wavelength < mu { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    let x = 1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2);
+    let y = 0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1);
+    let z = 1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8);
+    (x, y, z)
+}
+
+//Monochromatic light is outside the sRGB gamut, so the negative parts are dropped before the weights are normalised
+fn xyz_to_rgb((x, y, z): (f32, f32, f32)) -> Color {
+    Color {
+        red: (3.2406 * x - 1.5372 * y - 0.4986 * z).max(0.0),
+        green: (-0.9689 * x + 1.8758 * y + 0.0415 * z).max(0.0),
+        blue: (0.0557 * x - 0.2040 * y + 1.0570 * z).max(0.0),
+    }
+}
@@ -4,16 +4,27 @@ use crate::color::Color;
 use crate::scene::Intersectable;
 use crate::material::Material;
 use crate::material::TextureCoords;
+use crate::transform::Transform;
 use serde::{Serialize, Deserialize};
 
 #[derive(Clone, Deserialize)]
 pub struct Sphere {
     pub center: Vector3,
     pub radius: f64,
-    pub material: Material
+    #[serde(default)]
+    pub end_center: Option<Vector3>, //where the center has got to by the end of the frame, if the sphere moves
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
 }
 
 impl Sphere {
+    pub fn center_at(&self, time: f64) -> Vector3 {
+        match self.end_center {
+            Some(end) => self.center + (end - self.center) * time,
+            None => self.center,
+        }
+    }
     pub fn surface_normal(&self, hit_point: &Vector3) -> Vector3 {
         (*hit_point - self.center).normalize()
     }
@@ -24,11 +35,22 @@ impl Sphere {
             y: (hit_vec.y / self.radius).acos() as f32 / std::f32::consts::PI,
         }
     }
+    pub fn tangents(&self, hit_point: &Vector3) -> (Vector3, Vector3) { //directions in which the texture coords x and y increase
+        let hit_vec = (*hit_point - self.center).normalize();
+        let sin_theta = (hit_vec.x * hit_vec.x + hit_vec.z * hit_vec.z).sqrt();
+        if sin_theta < 1e-9 { //at the poles every direction is as good as any other
+            return (Vector3::new(0.0, 0.0, 1.0), Vector3::new(1.0, 0.0, 0.0));
+        }
+        let (cos_phi, sin_phi) = (hit_vec.x / sin_theta, hit_vec.z / sin_theta);
+        let dpdu = Vector3::new(-sin_phi, 0.0, cos_phi);
+        let dpdv = Vector3::new(hit_vec.y * cos_phi, -sin_theta, hit_vec.y * sin_phi);
+        (dpdu, dpdv)
+    }
 }
 
 impl Intersectable for Sphere {
     fn intersect(&self, ray: &Ray) -> Option<f64> {
-        let l: Vector3 = self.center - ray.origin;
+        let l: Vector3 = self.center_at(ray.time) - ray.origin;
         let adj = l.dot(&ray.direction);
         let d2 = l.dot(&l) - (adj * adj);
         let radius2 = self.radius * self.radius;
@@ -50,6 +72,18 @@ impl Intersectable for Sphere {
             Some(distance)
         }
     }
+
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(f64, f64)>> {
+        let l: Vector3 = self.center_at(ray.time) - ray.origin;
+        let adj = l.dot(&ray.direction);
+        let d2 = l.dot(&l) - (adj * adj);
+        let radius2 = self.radius * self.radius;
+        if d2 > radius2 {
+            return Some(Vec::new());
+        }
+        let thc = (radius2 - d2).sqrt();
+        Some(vec![(adj - thc, adj + thc)])
+    }
 }
 
 
@@ -0,0 +1,183 @@
+use crate::vector3::Vector3;
+use crate::material::Material;
+use crate::transform::Transform;
+use crate::mesh::{self, Mesh};
+use serde::{Deserialize, Deserializer};
+use serde::de::Error;
+use std::collections::HashMap;
+
+const MAX_LEVELS: u32 = 6; //each level makes four times as many faces
+
+fn default_levels() -> u32 {
+    2
+}
+
+//A polygon cage smoothed by Catmull-Clark subdivision when the scene is read e.g.
+//{ "Subdivision": { "vertices": [ ... ], "faces": [ [0, 1, 2, 3], ... ], "levels": 3, "material": ... }.
+//Faces list (0 based) vertex indices in order round the face and may have any number of sides; after the first
+//level all faces are quads. Edges with only one face are boundaries and stay put as curves through the cage.
+#[derive(Deserialize)]
+struct CageDesc {
+    vertices: Vec<Vector3>,
+    faces: Vec<Vec<usize>>,
+    #[serde(default = "default_levels")]
+    levels: u32,
+    material: Material,
+    #[serde(default)]
+    transform: Option<Transform>,
+}
+
+pub fn cage<'de, D>(deserializer: D) -> Result<Mesh, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let desc = CageDesc::deserialize(deserializer)?;
+    subdivide(desc).map_err(D::Error::custom)
+}
+
+fn subdivide(desc: CageDesc) -> Result<Mesh, String> {
+    if desc.levels > MAX_LEVELS {
+        return Err(format!("at most {} subdivision levels are allowed", MAX_LEVELS));
+    }
+    for face in &desc.faces {
+        if face.len() < 3 {
+            return Err(String::from("a subdivision face needs at least 3 vertices"));
+        }
+        if let Some(&index) = face.iter().find(|&&i| i >= desc.vertices.len()) {
+            return Err(format!("subdivision face vertex {} does not exist", index));
+        }
+    }
+    let (mut vertices, mut faces) = (desc.vertices, desc.faces);
+    for _ in 0..desc.levels {
+        let (v, f) = catmull_clark(&vertices, &faces);
+        vertices = v;
+        faces = f;
+    }
+    let mut triangles = Vec::new();
+    for face in &faces { //fans, which for the quads of a subdivided cage is just two triangles each
+        for k in 1..face.len() - 1 {
+            triangles.push([face[0], face[k], face[k + 1]]);
+        }
+    }
+    let normals = mesh::smooth_normals(&vertices, &triangles);
+    Mesh::new(vertices, normals, Vec::new(), triangles, desc.material, desc.transform)
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+fn average(points: &[Vector3]) -> Vector3 {
+    points.iter().fold(Vector3::zero(), |sum, &p| sum + p) * (1.0 / points.len() as f64)
+}
+
+//One level of subdivision. The new vertices are the moved old vertices, then a point on every edge, then a
+//point in the middle of every face, and each face of n sides becomes n quads round its middle point.
+fn catmull_clark(vertices: &[Vector3], faces: &[Vec<usize>]) -> (Vec<Vector3>, Vec<Vec<usize>>) {
+    let face_points: Vec<Vector3> = faces.iter().map(|f| average(&f.iter().map(|&i| vertices[i]).collect::<Vec<_>>())).collect();
+
+    let mut edge_faces: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    let mut edge_order = Vec::new(); //so that the new vertices come out in the same order every time
+    for (f, face) in faces.iter().enumerate() {
+        for k in 0..face.len() {
+            let key = edge_key(face[k], face[(k + 1) % face.len()]);
+            let adjacent = edge_faces.entry(key).or_default();
+            if adjacent.is_empty() {
+                edge_order.push(key);
+            }
+            adjacent.push(f);
+        }
+    }
+    let edge_index: HashMap<(usize, usize), usize> = edge_order.iter().enumerate().map(|(k, &key)| (key, vertices.len() + k)).collect();
+    let edge_points: Vec<Vector3> = edge_order.iter().map(|&(a, b)| {
+        let adjacent = &edge_faces[&(a, b)];
+        if adjacent.len() == 2 {
+            (vertices[a] + vertices[b] + face_points[adjacent[0]] + face_points[adjacent[1]]) * 0.25
+        } else {
+            (vertices[a] + vertices[b]) * 0.5
+        }
+    }).collect();
+
+    let mut vertex_faces = vec![Vec::new(); vertices.len()];
+    let mut vertex_edges = vec![Vec::new(); vertices.len()];
+    for (f, face) in faces.iter().enumerate() {
+        for &i in face {
+            vertex_faces[i].push(f);
+        }
+    }
+    for &(a, b) in &edge_order {
+        vertex_edges[a].push((a, b));
+        vertex_edges[b].push((a, b));
+    }
+    let moved: Vec<Vector3> = vertices.iter().enumerate().map(|(i, &p)| {
+        let boundary: Vec<Vector3> = vertex_edges[i].iter()
+            .filter(|key| edge_faces[key].len() != 2)
+            .map(|&(a, b)| vertices[if a == i { b } else { a }])
+            .collect();
+        if boundary.len() == 2 { //keep boundaries on the curve through the boundary edges
+            p * 0.75 + (boundary[0] + boundary[1]) * 0.125
+        } else if !boundary.is_empty() || vertex_faces[i].is_empty() { //corners and unused vertices stay where they are
+            p
+        } else {
+            let n = vertex_faces[i].len() as f64;
+            let f = average(&vertex_faces[i].iter().map(|&f| face_points[f]).collect::<Vec<_>>());
+            let r = average(&vertex_edges[i].iter().map(|&(a, b)| (vertices[a] + vertices[b]) * 0.5).collect::<Vec<_>>());
+            (f + r * 2.0 + p * (n - 3.0)) * (1.0 / n)
+        }
+    }).collect();
+
+    let face_base = vertices.len() + edge_order.len();
+    let mut new_faces = Vec::new();
+    for (f, face) in faces.iter().enumerate() {
+        let sides = face.len();
+        for k in 0..sides {
+            let (previous, current, next) = (face[(k + sides - 1) % sides], face[k], face[(k + 1) % sides]);
+            new_faces.push(vec![
+                current,
+                edge_index[&edge_key(current, next)],
+                face_base + f,
+                edge_index[&edge_key(previous, current)],
+            ]);
+        }
+    }
+    let mut new_vertices = moved;
+    new_vertices.extend(edge_points);
+    new_vertices.extend(face_points);
+    (new_vertices, new_faces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vector3, b: Vector3) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn cube_becomes_quads_and_shrinks() {
+        let vertices: Vec<Vector3> = (0..8).map(|i| Vector3::new(
+            if i & 1 == 0 { -1.0 } else { 1.0 },
+            if i & 2 == 0 { -1.0 } else { 1.0 },
+            if i & 4 == 0 { -1.0 } else { 1.0 },
+        )).collect();
+        let faces = vec![vec![0, 2, 3, 1], vec![4, 5, 7, 6], vec![0, 1, 5, 4], vec![2, 6, 7, 3], vec![0, 4, 6, 2], vec![1, 3, 7, 5]];
+        let (new_vertices, new_faces) = catmull_clark(&vertices, &faces);
+        assert_eq!(new_vertices.len(), 8 + 12 + 6);
+        assert_eq!(new_faces.len(), 24);
+        assert!(new_faces.iter().all(|f| f.len() == 4 && f.iter().all(|&i| i < new_vertices.len())));
+        assert_close(new_vertices[7], Vector3::new(5.0 / 9.0, 5.0 / 9.0, 5.0 / 9.0)); //(F + 2R + (n - 3)P) / n with n = 3
+        assert!(new_vertices.iter().any(|&v| (v - Vector3::new(0.75, 0.75, 0.0)).length() < 1e-9)); //edge from (1, 1, -1) to (1, 1, 1)
+        assert_close(new_vertices[8 + 12 + 1], Vector3::new(0.0, 0.0, 1.0)); //the middle of the +z face
+    }
+
+    #[test]
+    fn boundary_stays_on_its_curve() {
+        let vertices = vec![Vector3::new(0.0, 0.0, 0.0), Vector3::new(2.0, 0.0, 0.0), Vector3::new(2.0, 2.0, 0.0), Vector3::new(0.0, 2.0, 0.0)];
+        let (new_vertices, new_faces) = catmull_clark(&vertices, &[vec![0, 1, 2, 3]]);
+        assert_eq!((new_vertices.len(), new_faces.len()), (9, 4));
+        assert_close(new_vertices[0], Vector3::new(0.25, 0.25, 0.0));
+        assert_close(new_vertices[4], Vector3::new(1.0, 0.0, 0.0)); //boundary edges are split at their middle
+        assert_close(new_vertices[8], Vector3::new(1.0, 1.0, 0.0));
+    }
+}
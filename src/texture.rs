@@ -0,0 +1,360 @@
+use crate::color::{Color, ColorSpace};
+use crate::material::TextureCoords;
+use crate::asset;
+use image::{DynamicImage, GenericImageView};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::ops::{Add, Mul};
+extern crate base64;
+
+//A texture is either sent as a plain base64 string (assumed to be sRGB encoded) or as an object that also says
+//how the texels are encoded and sampled e.g. { "data": "...", "color_space": "Linear", "filter": "Trilinear", "wrap": "Mirror" }.
+//Textures uploaded to the asset store are referenced by name instead of data: { "asset": "checkerboard" }
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextureDesc {
+    Data(String),
+    Full(TextureSettings),
+}
+
+#[derive(Deserialize)]
+struct TextureSettings {
+    data: Option<String>,
+    asset: Option<String>,
+    #[serde(default)]
+    color_space: ColorSpace,
+    #[serde(default)]
+    filter: Filter,
+    #[serde(default)]
+    wrap: WrapMode,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+pub enum Filter {
+    #[default]
+    Nearest,
+    Bilinear,
+    Trilinear, //bilinear on the two closest mip levels, picked from the ray differentials of the hit
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "TextureDesc")]
+pub struct Texture {
+    pub image: Arc<DynamicImage>,
+    pub color_space: ColorSpace,
+    pub filter: Filter,
+    pub wrap: WrapMode,
+    mips: Arc<Vec<MipLevel>>, //levels 1..n in linear light, only built for trilinear filtering. Level 0 is the image itself
+}
+
+pub struct MipLevel {
+    width: u32,
+    height: u32,
+    texels: Vec<Color>,
+    alpha: Vec<f32>,
+}
+
+//How much the texture coordinates change from one pixel to the next, in texture coordinate units
+pub struct Footprint {
+    pub dudx: f32,
+    pub dvdx: f32,
+    pub dudy: f32,
+    pub dvdy: f32,
+}
+
+impl TryFrom<TextureDesc> for Texture {
+    type Error = String;
+
+    fn try_from(desc: TextureDesc) -> Result<Self, Self::Error> {
+        let settings = match desc {
+            TextureDesc::Data(data) => return Ok(Texture::new(decode(&data)?, ColorSpace::Srgb)),
+            TextureDesc::Full(settings) => settings,
+        };
+        match (settings.data, settings.asset) {
+            (Some(data), None) => Ok(Texture::from_shared(Arc::new(decode(&data)?), settings.color_space, settings.filter, settings.wrap)),
+            (None, Some(name)) => {
+                let asset = asset::lookup(&name)?;
+                let mips = if settings.filter == Filter::Trilinear { asset.mips(settings.color_space) } else { Arc::new(Vec::new()) };
+                Ok(Texture {
+                    image: asset.image.clone(),
+                    color_space: settings.color_space,
+                    filter: settings.filter,
+                    wrap: settings.wrap,
+                    mips, //built once per asset rather than on every render that uses it
+                })
+            },
+            _ => Err(String::from("a texture needs exactly one of data or asset")),
+        }
+    }
+}
+
+fn decode(data: &str) -> Result<DynamicImage, String> {
+    let bytes = base64::decode(data).map_err(|e| format!("texture is not valid base64: {}", e))?;
+    image::load_from_memory(&bytes).map_err(|e| format!("texture could not be decoded: {}", e))
+}
+
+impl Texture {
+    pub fn new(image: DynamicImage, color_space: ColorSpace) -> Texture {
+        Texture::from_shared(Arc::new(image), color_space, Filter::Nearest, WrapMode::Repeat)
+    }
+
+    pub fn from_shared(image: Arc<DynamicImage>, color_space: ColorSpace, filter: Filter, wrap: WrapMode) -> Texture {
+        let mips = if filter == Filter::Trilinear { build_mips(&image, color_space) } else { Vec::new() };
+        Texture {
+            image,
+            color_space,
+            filter,
+            wrap,
+            mips: Arc::new(mips),
+        }
+    }
+
+    pub fn color(&self, texture_coords: &TextureCoords) -> Color {
+        self.sample(texture_coords, None)
+    }
+
+    pub fn sample(&self, texture_coords: &TextureCoords, footprint: Option<&Footprint>) -> Color {
+        self.filtered(texture_coords, footprint, |level, x, y| self.texel(level, x, y))
+    }
+
+    //coverage of the texel, 1.0 everywhere for images without an alpha channel
+    pub fn alpha(&self, texture_coords: &TextureCoords, footprint: Option<&Footprint>) -> f32 {
+        if !self.has_alpha() {
+            return 1.0;
+        }
+        self.filtered(texture_coords, footprint, |level, x, y| self.texel_alpha(level, x, y))
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        self.image.color().has_alpha()
+    }
+
+    //colors and alpha go through the same filtering, only the texel fetch differs
+    fn filtered<T, F>(&self, texture_coords: &TextureCoords, footprint: Option<&Footprint>, fetch: F) -> T
+    where
+        T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+        F: Fn(usize, i64, i64) -> T,
+    {
+        match self.filter {
+            Filter::Nearest => {
+                let (width, height) = self.size(0);
+                let x = (texture_coords.x * width as f32).floor() as i64;
+                let y = (texture_coords.y * height as f32).floor() as i64;
+                fetch(0, x, y)
+            },
+            Filter::Bilinear => self.bilinear(0, texture_coords, &fetch),
+            Filter::Trilinear => {
+                let level = footprint.map(|f| self.level_of_detail(f)).unwrap_or(0.0); //no differentials (e.g. secondary rays) means full resolution
+                let lower = level.floor() as usize;
+                if lower >= self.mips.len() {
+                    return self.bilinear(self.mips.len(), texture_coords, &fetch);
+                }
+                let t = level - lower as f32;
+                self.bilinear(lower, texture_coords, &fetch) * (1.0 - t) + self.bilinear(lower + 1, texture_coords, &fetch) * t
+            },
+        }
+    }
+
+    fn level_of_detail(&self, footprint: &Footprint) -> f32 {
+        let (width, height) = (self.image.width() as f32, self.image.height() as f32);
+        let dx = ((footprint.dudx * width).powi(2) + (footprint.dvdx * height).powi(2)).sqrt();
+        let dy = ((footprint.dudy * width).powi(2) + (footprint.dvdy * height).powi(2)).sqrt();
+        let texels_per_pixel = dx.max(dy);
+        if texels_per_pixel.is_finite() && texels_per_pixel > 1.0 {
+            texels_per_pixel.log2().min(self.mips.len() as f32)
+        } else {
+            0.0
+        }
+    }
+
+    fn bilinear<T, F>(&self, level: usize, texture_coords: &TextureCoords, fetch: &F) -> T
+    where
+        T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+        F: Fn(usize, i64, i64) -> T,
+    {
+        let (width, height) = self.size(level);
+        let x = texture_coords.x * width as f32 - 0.5; //texel centers sit at half coordinates
+        let y = texture_coords.y * height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = fetch(level, x0, y0) * (1.0 - fx) + fetch(level, x0 + 1, y0) * fx;
+        let bottom = fetch(level, x0, y0 + 1) * (1.0 - fx) + fetch(level, x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    fn size(&self, level: usize) -> (u32, u32) {
+        if level == 0 {
+            (self.image.width(), self.image.height())
+        } else {
+            (self.mips[level - 1].width, self.mips[level - 1].height)
+        }
+    }
+
+    fn texel(&self, level: usize, x: i64, y: i64) -> Color {
+        let (width, height) = self.size(level);
+        let x = wrap(x, width, self.wrap);
+        let y = wrap(y, height, self.wrap);
+        if level == 0 {
+            Color::from_rgba_in(self.image.get_pixel(x, y), self.color_space)
+        } else {
+            let mip = &self.mips[level - 1];
+            mip.texels[(y * mip.width + x) as usize]
+        }
+    }
+
+    fn texel_alpha(&self, level: usize, x: i64, y: i64) -> f32 { //alpha is linear coverage, never gamma encoded
+        let (width, height) = self.size(level);
+        let x = wrap(x, width, self.wrap);
+        let y = wrap(y, height, self.wrap);
+        if level == 0 {
+            self.image.get_pixel(x, y)[3] as f32 / 255.0
+        } else {
+            let mip = &self.mips[level - 1];
+            mip.alpha[(y * mip.width + x) as usize]
+        }
+    }
+}
+
+//Levels 1..n of an image read in color_space: each a box filtered half of the one before until a single texel is
+//left, averaged in linear light
+pub fn build_mips(image: &DynamicImage, color_space: ColorSpace) -> Vec<MipLevel> {
+    let mut mips: Vec<MipLevel> = Vec::new();
+    let (mut width, mut height) = (image.width(), image.height());
+    while width > 1 || height > 1 {
+        let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+        let mut texels = Vec::with_capacity((next_width * next_height) as usize);
+        let mut alpha = Vec::with_capacity((next_width * next_height) as usize);
+        for y in 0..next_height {
+            for x in 0..next_width {
+                let mut sum = Color { red: 0.0, green: 0.0, blue: 0.0 };
+                let mut alpha_sum = 0.0;
+                for (dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let sx = (x * 2 + dx).min(width - 1);
+                    let sy = (y * 2 + dy).min(height - 1);
+                    let (color, coverage) = match mips.last() {
+                        None => {
+                            let pixel = image.get_pixel(sx, sy);
+                            (Color::from_rgba_in(pixel, color_space), pixel[3] as f32 / 255.0)
+                        },
+                        Some(prev) => {
+                            let index = (sy * prev.width + sx) as usize;
+                            (prev.texels[index], prev.alpha[index])
+                        },
+                    };
+                    sum = sum + color;
+                    alpha_sum += coverage;
+                }
+                texels.push(sum * 0.25);
+                alpha.push(alpha_sum * 0.25);
+            }
+        }
+        mips.push(MipLevel { width: next_width, height: next_height, texels, alpha });
+        width = next_width;
+        height = next_height;
+    }
+    mips
+}
+
+fn wrap(coord: i64, bound: u32, mode: WrapMode) -> u32 { //to make sure that we do not go outside the sample texture file we are using as our texture i.e. DynamicImage
+    let bound = bound as i64;
+    match mode {
+        WrapMode::Repeat => coord.rem_euclid(bound) as u32,
+        WrapMode::Clamp => coord.max(0).min(bound - 1) as u32,
+        WrapMode::Mirror => {
+            let period = coord.rem_euclid(bound * 2);
+            if period < bound { period as u32 } else { (bound * 2 - 1 - period) as u32 }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    //A grey image in linear light, so a texel of value v reads back as v / 255 in every channel
+    fn gray(width: u32, height: u32, values: &[u8], filter: Filter, wrap: WrapMode) -> Texture {
+        let image = GrayImage::from_fn(width, height, |x, y| Luma([values[(y * width + x) as usize]]));
+        Texture::from_shared(Arc::new(DynamicImage::ImageLuma8(image)), ColorSpace::Linear, filter, wrap)
+    }
+
+    fn at(texture: &Texture, x: f32, y: f32, footprint: Option<&Footprint>) -> f32 {
+        texture.sample(&TextureCoords { x, y }, footprint).red * 255.0
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-3, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn wraps_coordinates_outside_the_image() {
+        let coords: Vec<i64> = (-4..8).collect();
+        let wrapped = |mode| coords.iter().map(|&c| wrap(c, 3, mode)).collect::<Vec<u32>>();
+        assert_eq!(wrapped(WrapMode::Repeat), vec![2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1]);
+        assert_eq!(wrapped(WrapMode::Clamp), vec![0, 0, 0, 0, 0, 1, 2, 2, 2, 2, 2, 2]);
+        assert_eq!(wrapped(WrapMode::Mirror), vec![2, 2, 1, 0, 0, 1, 2, 2, 1, 0, 0, 1]);
+    }
+
+    #[test]
+    fn nearest_picks_the_texel_under_the_coordinates() {
+        let texture = gray(2, 1, &[0, 200], Filter::Nearest, WrapMode::Repeat);
+        assert_close(at(&texture, 0.49, 0.5, None), 0.0);
+        assert_close(at(&texture, 0.51, 0.5, None), 200.0);
+        assert_close(at(&texture, 1.25, 0.5, None), 0.0); //repeated
+    }
+
+    #[test]
+    fn bilinear_weights_by_distance_to_texel_centers() {
+        let texture = gray(2, 2, &[0, 200, 100, 100], Filter::Bilinear, WrapMode::Clamp);
+        assert_close(at(&texture, 0.25, 0.25, None), 0.0); //on a texel center
+        assert_close(at(&texture, 0.375, 0.25, None), 50.0); //a quarter of the way to the next one
+        assert_close(at(&texture, 0.5, 0.25, None), 100.0);
+        assert_close(at(&texture, 0.5, 0.5, None), 100.0); //all four equally
+        assert_close(at(&texture, 0.25, 0.5, None), 50.0);
+        assert_close(at(&texture, 0.0, 0.25, None), 0.0); //clamped beyond the edge
+        let repeated = gray(2, 2, &[0, 200, 100, 100], Filter::Bilinear, WrapMode::Repeat);
+        assert_close(at(&repeated, 0.0, 0.25, None), 100.0); //blends with the far side
+    }
+
+    #[test]
+    fn mips_halve_until_one_texel_in_linear_light() {
+        let image = DynamicImage::ImageLuma8(GrayImage::from_fn(4, 2, |x, _| Luma([if x < 2 { 0 } else { 255 }])));
+        let mips = build_mips(&image, ColorSpace::Linear);
+        let sizes: Vec<(u32, u32)> = mips.iter().map(|m| (m.width, m.height)).collect();
+        assert_eq!(sizes, vec![(2, 1), (1, 1)]);
+        assert_close(mips[0].texels[0].red, 0.0);
+        assert_close(mips[0].texels[1].red, 1.0);
+        assert_close(mips[1].texels[0].red, 0.5);
+        assert_close(mips[1].alpha[0], 1.0);
+
+        let srgb = build_mips(&image, ColorSpace::Srgb);
+        assert_close(srgb[1].texels[0].red, 0.5); //averaged after decoding, not on the encoded values
+
+        let odd = build_mips(&DynamicImage::ImageLuma8(GrayImage::new(3, 3)), ColorSpace::Linear);
+        assert_eq!(odd.iter().map(|m| (m.width, m.height)).collect::<Vec<_>>(), vec![(1, 1)]);
+    }
+
+    #[test]
+    fn picks_the_mip_level_from_the_footprint() {
+        let texture = gray(8, 8, &[100; 64], Filter::Trilinear, WrapMode::Repeat);
+        let footprint = |texels: f32| Footprint { dudx: texels / 8.0, dvdx: 0.0, dudy: 0.0, dvdy: texels / 8.0 };
+        assert_close(texture.level_of_detail(&footprint(0.5)), 0.0); //magnified
+        assert_close(texture.level_of_detail(&footprint(1.0)), 0.0);
+        assert_close(texture.level_of_detail(&footprint(2.0)), 1.0);
+        assert_close(texture.level_of_detail(&footprint(4.0)), 2.0);
+        assert_close(texture.level_of_detail(&footprint(64.0)), 3.0); //no level below a single texel
+        assert_close(texture.level_of_detail(&footprint(f32::NAN)), 0.0);
+        assert_close(at(&texture, 0.3, 0.6, Some(&footprint(3.0))), 100.0); //between levels of a flat image
+    }
+}
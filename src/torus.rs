@@ -0,0 +1,255 @@
+use crate::vector3::Vector3;
+use crate::ray::Ray;
+use crate::scene::Intersectable;
+use crate::material::Material;
+use crate::material::TextureCoords;
+use crate::transform::Transform;
+use crate::frame::Frame;
+use crate::cylinder::up;
+use serde::Deserialize;
+
+//A ring around axis through center: a tube of minor_radius swept around a circle of major_radius.
+//Texture x runs around the ring and y around the tube, starting from its outer edge.
+#[derive(Clone, Deserialize)]
+pub struct Torus {
+    pub center: Vector3,
+    #[serde(default = "up")]
+    pub axis: Vector3,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+}
+
+impl Torus {
+    pub fn surface_normal(&self, hit_point: &Vector3) -> Vector3 {
+        let frame = self.frame();
+        let p = frame.to_local(hit_point);
+        let ring = self.nearest_on_ring(&p);
+        frame.to_world_direction(&(p - ring)).normalize()
+    }
+
+    pub fn texture_coords(&self, hit_point: &Vector3) -> TextureCoords {
+        let p = self.frame().to_local(hit_point);
+        let radial = (p.x * p.x + p.z * p.z).sqrt();
+        let tau = 2.0 * std::f64::consts::PI;
+        TextureCoords {
+            x: ((p.z.atan2(p.x) / tau) + 0.5) as f32,
+            y: (p.y.atan2(radial - self.major_radius) / tau).rem_euclid(1.0) as f32,
+        }
+    }
+
+    pub fn tangents(&self, hit_point: &Vector3) -> (Vector3, Vector3) {
+        let frame = self.frame();
+        let p = frame.to_local(hit_point);
+        let phi = p.z.atan2(p.x);
+        let radial = (p.x * p.x + p.z * p.z).sqrt();
+        let theta = p.y.atan2(radial - self.major_radius);
+        let outward = Vector3::new(phi.cos(), 0.0, phi.sin());
+        let around_ring = Vector3::new(-phi.sin(), 0.0, phi.cos());
+        let around_tube = outward * -theta.sin() + Vector3::new(0.0, theta.cos(), 0.0);
+        (frame.to_world_direction(&around_ring), frame.to_world_direction(&around_tube))
+    }
+
+    fn frame(&self) -> Frame {
+        Frame::new(self.center, self.axis)
+    }
+
+    fn nearest_on_ring(&self, p: &Vector3) -> Vector3 {
+        let radial = (p.x * p.x + p.z * p.z).sqrt().max(1e-12);
+        Vector3::new(p.x / radial, 0.0, p.z / radial) * self.major_radius
+    }
+}
+
+impl Intersectable for Torus {
+    //Substituting the ray into (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2) gives a quartic in t. The ray is first
+    //moved up to the bounding sphere so the coefficients stay small enough to solve accurately from far away.
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let (o, d, entry, scale) = self.local_ray(ray)?;
+        let skip = entry.max(0.0);
+        let coefficients = self.coefficients(&(o + d * skip), &d);
+        solve_quartic(&coefficients)
+            .into_iter()
+            .map(|t| polish(&coefficients, t))
+            .filter(|&t| t + skip > 1e-9)
+            .fold(None, |nearest: Option<f64>, t| Some(nearest.map_or(t, |n| n.min(t))))
+            .map(|t| (t + skip) / scale)
+    }
+
+    //Between each pair of neighbouring roots the ray is either inside or outside the tube all the way, so the
+    //midpoint tells which; this also copes with the doubled root of a ray grazing the surface
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(f64, f64)>> {
+        let (o, d, entry, scale) = match self.local_ray(ray) {
+            Some(local) => local,
+            None => return Some(Vec::new()),
+        };
+        let o = o + d * entry;
+        let coefficients = self.coefficients(&o, &d);
+        let mut roots: Vec<f64> = solve_quartic(&coefficients).into_iter().map(|t| polish(&coefficients, t)).collect();
+        roots.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let intervals = roots.windows(2)
+            .filter(|pair| evaluate(&coefficients, (pair[0] + pair[1]) * 0.5).0 < 0.0)
+            .map(|pair| ((pair[0] + entry) / scale, (pair[1] + entry) / scale))
+            .collect();
+        Some(intervals)
+    }
+}
+
+impl Torus {
+    //The ray in the torus' frame with a unit direction, how far along it the bounding sphere starts (which may be
+    //behind the origin) and how much longer t along the unit direction is than along the ray's own; None if the
+    //line misses the bounding sphere
+    fn local_ray(&self, ray: &Ray) -> Option<(Vector3, Vector3, f64, f64)> {
+        let frame = self.frame();
+        let o = frame.to_local(&ray.origin);
+        let d = frame.to_local_direction(&ray.direction).normalize();
+        let scale = ray.direction.length();
+
+        let bound = self.major_radius + self.minor_radius;
+        let b = o.dot(&d);
+        let c = o.dot(&o) - bound * bound;
+        let discriminant = b * b - c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        Some((o, d, -b - discriminant.sqrt(), scale))
+    }
+
+    //Of the quartic in t, lowest power first, which is negative inside the tube
+    fn coefficients(&self, o: &Vector3, d: &Vector3) -> [f64; 5] {
+        let (big, small) = (self.major_radius, self.minor_radius);
+        let f = o.dot(d);
+        let e = o.dot(o) - big * big - small * small;
+        let four_big2 = 4.0 * big * big;
+        [
+            e * e + four_big2 * (o.y * o.y - small * small),
+            4.0 * f * e + 2.0 * four_big2 * o.y * d.y,
+            4.0 * f * f + 2.0 * e + four_big2 * d.y * d.y,
+            4.0 * f,
+            1.0,
+        ]
+    }
+}
+
+fn evaluate(c: &[f64; 5], t: f64) -> (f64, f64) { //the polynomial and its derivative at t
+    let value = (((c[4] * t + c[3]) * t + c[2]) * t + c[1]) * t + c[0];
+    let slope = ((4.0 * c[4] * t + 3.0 * c[3]) * t + 2.0 * c[2]) * t + c[1];
+    (value, slope)
+}
+
+fn polish(c: &[f64; 5], mut t: f64) -> f64 { //a couple of Newton steps to win back precision lost in the closed form
+    for _ in 0..2 {
+        let (value, slope) = evaluate(c, t);
+        if slope.abs() < 1e-12 {
+            break;
+        }
+        t -= value / slope;
+    }
+    t
+}
+
+const EPSILON: f64 = 1e-9;
+
+//Real roots of c[0] + c[1] x + c[2] x^2 + c[3] x^3 + c[4] x^4, by Ferrari's method as laid out by Schwarze in Graphics Gems
+fn solve_quartic(c: &[f64; 5]) -> Vec<f64> {
+    let (a, b, cc, d) = (c[3] / c[4], c[2] / c[4], c[1] / c[4], c[0] / c[4]);
+    //substitute x = y - a/4 to get rid of the cubic term: y^4 + p y^2 + q y + r = 0
+    let sq_a = a * a;
+    let p = -3.0 / 8.0 * sq_a + b;
+    let q = 1.0 / 8.0 * sq_a * a - 0.5 * a * b + cc;
+    let r = -3.0 / 256.0 * sq_a * sq_a + 1.0 / 16.0 * sq_a * b - 0.25 * a * cc + d;
+
+    let mut roots = Vec::new();
+    if r.abs() < EPSILON {
+        roots.push(0.0);
+        roots.extend(solve_cubic(&[q, p, 0.0, 1.0]));
+    } else {
+        let z = solve_cubic(&[0.5 * r * p - 0.125 * q * q, -r, -0.5 * p, 1.0])[0];
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        let u = if u.abs() < EPSILON { 0.0 } else if u > 0.0 { u.sqrt() } else { return roots };
+        let v = if v.abs() < EPSILON { 0.0 } else if v > 0.0 { v.sqrt() } else { return roots };
+        roots.extend(solve_quadratic(z - u, if q < 0.0 { -v } else { v }, 1.0));
+        roots.extend(solve_quadratic(z + u, if q < 0.0 { v } else { -v }, 1.0));
+    }
+    roots.into_iter().map(|y| y - 0.25 * a).collect()
+}
+
+//Real roots of c[0] + c[1] x + c[2] x^2 + c[3] x^3, never empty
+fn solve_cubic(c: &[f64; 4]) -> Vec<f64> {
+    let (a, b, cc) = (c[2] / c[3], c[1] / c[3], c[0] / c[3]);
+    let sq_a = a * a;
+    let p = 1.0 / 3.0 * (-1.0 / 3.0 * sq_a + b);
+    let q = 0.5 * (2.0 / 27.0 * a * sq_a - 1.0 / 3.0 * a * b + cc);
+    let cb_p = p * p * p;
+    let discriminant = q * q + cb_p;
+
+    let roots = if discriminant.abs() < EPSILON {
+        if q.abs() < EPSILON {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if discriminant < 0.0 { //three real roots
+        let phi = 1.0 / 3.0 * (-q / (-cb_p).sqrt()).clamp(-1.0, 1.0).acos();
+        let t = 2.0 * (-p).sqrt();
+        let third = std::f64::consts::PI / 3.0;
+        vec![t * phi.cos(), -t * (phi + third).cos(), -t * (phi - third).cos()]
+    } else {
+        let root = discriminant.sqrt();
+        vec![(root - q).cbrt() - (root + q).cbrt()]
+    };
+    roots.into_iter().map(|y| y - 1.0 / 3.0 * a).collect()
+}
+
+//Real roots of c0 + c1 x + c2 x^2
+fn solve_quadratic(c0: f64, c1: f64, c2: f64) -> Vec<f64> {
+    let p = c1 / (2.0 * c2);
+    let q = c0 / c2;
+    let discriminant = p * p - q;
+    if discriminant.abs() < EPSILON {
+        vec![-p]
+    } else if discriminant < 0.0 {
+        Vec::new()
+    } else {
+        let root = discriminant.sqrt();
+        vec![root - p, -root - p]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(c: [f64; 5]) -> Vec<f64> {
+        let mut roots = solve_quartic(&c);
+        roots.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        roots
+    }
+
+    fn assert_roots(roots: &[f64], expected: &[f64]) {
+        assert_eq!(roots.len(), expected.len(), "{:?} != {:?}", roots, expected);
+        for (root, expected) in roots.iter().zip(expected) {
+            assert!((root - expected).abs() < 1e-6, "{:?} != {:?}", roots, expected);
+        }
+    }
+
+    #[test]
+    fn four_real_roots() {
+        assert_roots(&sorted([24.0, -50.0, 35.0, -10.0, 1.0]), &[1.0, 2.0, 3.0, 4.0]); //(x - 1)(x - 2)(x - 3)(x - 4)
+        assert_roots(&sorted([48.0, -100.0, 70.0, -20.0, 2.0]), &[1.0, 2.0, 3.0, 4.0]); //not monic
+    }
+
+    #[test]
+    fn two_real_roots() {
+        assert_roots(&sorted([-1.0, 0.0, 0.0, 0.0, 1.0]), &[-1.0, 1.0]); //x^4 - 1
+    }
+
+    #[test]
+    fn no_real_roots() {
+        assert!(solve_quartic(&[1.0, 0.0, 0.0, 0.0, 1.0]).is_empty()); //x^4 + 1
+        assert!(solve_quartic(&[5.0, 0.0, 3.0, 0.0, 1.0]).is_empty()); //x^4 + 3x^2 + 5
+    }
+}
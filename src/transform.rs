@@ -0,0 +1,423 @@
+use crate::vector3::Vector3;
+use crate::ray::Ray;
+use crate::cuboid::rotation_axes;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+//Places an element in the scene. Written either as parts applied scale first, then rotate (degrees about x, then y,
+//then z), then translate e.g. { "scale": [2, 1, 1], "rotate": { "x": 0, "y": 45, "z": 0 }, "translate": { ... } },
+//or as a row major 4x4 affine matrix: { "matrix": [[1, 0, 0, 2], [0, 1, 0, 0], [0, 0, 1, 0], [0, 0, 0, 1]] }.
+//Something that moves during the frame also says where it ends up in parts: { "translate": { ... }, "end": { "translate": { ... } } }.
+//Each part moves on its own, translate and scale in a straight line and rotate the shorter way round, so a turning
+//element keeps its shape; a matrix cannot be pulled apart like that, so it can only be used for things that stay put
+#[derive(Deserialize)]
+struct MovingDesc {
+    #[serde(flatten)]
+    start: TransformDesc,
+    #[serde(default)]
+    end: Option<TransformDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TransformDesc {
+    Matrix { matrix: [[f64; 4]; 4] },
+    Parts {
+        #[serde(default = "Vector3::zero")]
+        translate: Vector3,
+        #[serde(default = "Vector3::zero")]
+        rotate: Vector3,
+        #[serde(default)]
+        scale: Scale,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Scale {
+    Uniform(f64),
+    PerAxis([f64; 3]),
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Scale::Uniform(1.0)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "MovingDesc")]
+pub struct Transform {
+    matrix: [[f64; 4]; 4], //object to world (at the start of the frame, for things that move)
+    inverse: [[f64; 4]; 4], //world to object
+    motion: Option<Arc<Vec<Factor>>>, //for things that move, the transforms that make this one, outermost first
+}
+
+//One of the transforms a moving one is made of: either one that stays put or one moving between two sets of parts
+#[derive(Clone, Debug)]
+enum Factor {
+    Fixed { matrix: [[f64; 4]; 4], inverse: [[f64; 4]; 4] },
+    Moving { start: Parts, end: Parts },
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Parts {
+    translate: Vector3,
+    rotation: Quaternion,
+    scale: [f64; 3],
+}
+
+impl TryFrom<MovingDesc> for Transform {
+    type Error = String;
+
+    fn try_from(desc: MovingDesc) -> Result<Self, Self::Error> {
+        let end = match desc.end {
+            Some(end) => end,
+            None => return Transform::new(desc_matrix(desc.start)),
+        };
+        let (start, end) = match (parts(&desc.start), parts(&end)) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Err(String::from("a moving transform must be written as translate, rotate and scale, not a matrix")),
+        };
+        if (0..3).any(|axis| start.scale[axis] * end.scale[axis] <= 0.0) {
+            return Err(String::from("a moving transform cannot scale through zero"));
+        }
+        let transform = Transform::new(desc_matrix(desc.start))?;
+        Ok(Transform { motion: Some(Arc::new(vec![Factor::Moving { start, end }])), ..transform })
+    }
+}
+
+fn parts(desc: &TransformDesc) -> Option<Parts> {
+    match *desc {
+        TransformDesc::Matrix { .. } => None,
+        TransformDesc::Parts { translate, rotate, ref scale } => Some(Parts {
+            translate,
+            rotation: Quaternion::from_rotation(&rotate),
+            scale: scale.per_axis(),
+        }),
+    }
+}
+
+impl Scale {
+    fn per_axis(&self) -> [f64; 3] {
+        match *self {
+            Scale::Uniform(s) => [s, s, s],
+            Scale::PerAxis(s) => s,
+        }
+    }
+}
+
+fn desc_matrix(desc: TransformDesc) -> [[f64; 4]; 4] {
+    match desc {
+        TransformDesc::Matrix { matrix } => matrix,
+        TransformDesc::Parts { translate, rotate, scale } => {
+            let [sx, sy, sz] = scale.per_axis();
+            let axes = rotation_axes(&rotate);
+            let column = |axis: usize, s: f64| [axes[axis].x * s, axes[axis].y * s, axes[axis].z * s];
+            let (x, y, z) = (column(0, sx), column(1, sy), column(2, sz));
+            [
+                [x[0], y[0], z[0], translate.x],
+                [x[1], y[1], z[1], translate.y],
+                [x[2], y[2], z[2], translate.z],
+                [0.0, 0.0, 0.0, 1.0],
+            ]
+        },
+    }
+}
+
+impl Transform {
+    pub fn new(matrix: [[f64; 4]; 4]) -> Result<Transform, String> {
+        let inverse = invert(&matrix).ok_or_else(|| String::from("transform cannot be inverted (is a scale zero?)"))?;
+        Ok(Transform { matrix, inverse, motion: None })
+    }
+
+    //inner first, then self: how a child of a group is placed by its own transform and then the group's
+    pub fn compose(&self, inner: &Transform) -> Transform {
+        let motion = match (&self.motion, &inner.motion) {
+            (None, None) => None,
+            _ => Some(Arc::new(self.factors().into_iter().chain(inner.factors()).collect())),
+        };
+        Transform {
+            matrix: multiply(&self.matrix, &inner.matrix),
+            inverse: multiply(&inner.inverse, &self.inverse),
+            motion,
+        }
+    }
+
+    fn factors(&self) -> Vec<Factor> {
+        match self.motion {
+            Some(ref factors) => factors.to_vec(),
+            None => vec![Factor::Fixed { matrix: self.matrix, inverse: self.inverse }],
+        }
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.motion.is_some()
+    }
+
+    //Where the transform has got to at a time from 0 (the start of the frame) to 1 (its end)
+    pub fn at(&self, time: f64) -> Transform {
+        let factors = match self.motion {
+            Some(ref factors) => factors,
+            None => return self.clone(),
+        };
+        let identity = Transform { matrix: IDENTITY, inverse: IDENTITY, motion: None };
+        factors.iter().fold(identity, |outer, factor| {
+            let (matrix, inverse) = factor.at(time);
+            outer.compose(&Transform { matrix, inverse, motion: None })
+        })
+    }
+
+    pub fn point_to_world(&self, point: &Vector3) -> Vector3 {
+        apply(&self.matrix, point, 1.0)
+    }
+
+    pub fn point_to_local(&self, point: &Vector3) -> Vector3 {
+        apply(&self.inverse, point, 1.0)
+    }
+
+    pub fn direction_to_world(&self, direction: &Vector3) -> Vector3 {
+        apply(&self.matrix, direction, 0.0)
+    }
+
+    pub fn direction_to_local(&self, direction: &Vector3) -> Vector3 {
+        apply(&self.inverse, direction, 0.0)
+    }
+
+    //Normals go through the inverse transpose so they stay at right angles to surfaces that were scaled unevenly
+    pub fn normal_to_world(&self, normal: &Vector3) -> Vector3 {
+        let m = &self.inverse;
+        Vector3::new(
+            m[0][0] * normal.x + m[1][0] * normal.y + m[2][0] * normal.z,
+            m[0][1] * normal.x + m[1][1] * normal.y + m[2][1] * normal.z,
+            m[0][2] * normal.x + m[1][2] * normal.y + m[2][2] * normal.z,
+        ).normalize()
+    }
+
+    //The ray in object space with a unit direction, and how much longer a distance along it is than the same
+    //stretch of the world ray, so that distance / stretch is a distance along the world ray
+    pub fn ray_to_local(&self, ray: &Ray) -> (Ray, f64) {
+        let direction = self.direction_to_local(&ray.direction);
+        let stretch = direction.length();
+        let local = Ray {
+            origin: self.point_to_local(&ray.origin),
+            direction: direction * (1.0 / stretch),
+            differential: None,
+            time: ray.time,
+        };
+        (local, stretch)
+    }
+}
+
+const IDENTITY: [[f64; 4]; 4] = [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]];
+
+impl Factor {
+    //The matrix and its inverse at a time. A moving one is put together from its parts, translate * rotate * scale,
+    //and taken apart the other way round for the inverse, which never goes flat as the scale cannot reach zero
+    fn at(&self, time: f64) -> ([[f64; 4]; 4], [[f64; 4]; 4]) {
+        let (start, end) = match *self {
+            Factor::Fixed { matrix, inverse } => return (matrix, inverse),
+            Factor::Moving { ref start, ref end } => (start, end),
+        };
+        let translate = start.translate + (end.translate - start.translate) * time;
+        let rotation = start.rotation.slerp(&end.rotation, time).matrix();
+        let lerp = |a: f64, b: f64| a + (b - a) * time;
+        let scale = [lerp(start.scale[0], end.scale[0]), lerp(start.scale[1], end.scale[1]), lerp(start.scale[2], end.scale[2])];
+        let t = [translate.x, translate.y, translate.z];
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for row in 0..3 {
+            for column in 0..3 {
+                matrix[row][column] = rotation[row][column] * scale[column];
+                inverse[row][column] = rotation[column][row] / scale[row]; //a rotation's inverse is its transpose
+            }
+            matrix[row][3] = t[row];
+        }
+        for row in inverse.iter_mut().take(3) {
+            row[3] = -(0..3).map(|k| row[k] * t[k]).sum::<f64>();
+        }
+        (matrix, inverse)
+    }
+}
+
+//A unit quaternion w + xi + yj + zk, the form of a rotation that can be blended smoothly between two others
+#[derive(Clone, Copy, Debug)]
+struct Quaternion {
+    w: f64,
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+impl Quaternion {
+    //The same turn as rotation_axes: degrees about x, then y, then z
+    fn from_rotation(rotation: &Vector3) -> Quaternion {
+        let about = |angle: f64, axis: usize| {
+            let (sin, cos) = (angle.to_radians() * 0.5).sin_cos();
+            let mut q = Quaternion { w: cos, x: 0.0, y: 0.0, z: 0.0 };
+            match axis {
+                0 => q.x = sin,
+                1 => q.y = sin,
+                _ => q.z = sin,
+            }
+            q
+        };
+        about(rotation.z, 2).multiply(&about(rotation.y, 1)).multiply(&about(rotation.x, 0))
+    }
+
+    fn multiply(&self, other: &Quaternion) -> Quaternion {
+        Quaternion {
+            w: self.w * other.w - self.x * other.x - self.y * other.y - self.z * other.z,
+            x: self.w * other.x + self.x * other.w + self.y * other.z - self.z * other.y,
+            y: self.w * other.y - self.x * other.z + self.y * other.w + self.z * other.x,
+            z: self.w * other.z + self.x * other.y - self.y * other.x + self.z * other.w,
+        }
+    }
+
+    //Along the shorter arc between the two at an even speed
+    fn slerp(&self, other: &Quaternion, time: f64) -> Quaternion {
+        let mut cos = self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z;
+        let mut other = *other;
+        if cos < 0.0 { //q and -q are the same rotation, the one closer to self is the shorter way round
+            other = Quaternion { w: -other.w, x: -other.x, y: -other.y, z: -other.z };
+            cos = -cos;
+        }
+        let (a, b) = if cos > 0.9995 { //so close that a straight blend is as good and avoids dividing by a tiny sine
+            (1.0 - time, time)
+        } else {
+            let angle = cos.acos();
+            (((1.0 - time) * angle).sin() / angle.sin(), (time * angle).sin() / angle.sin())
+        };
+        let q = Quaternion {
+            w: self.w * a + other.w * b,
+            x: self.x * a + other.x * b,
+            y: self.y * a + other.y * b,
+            z: self.z * a + other.z * b,
+        };
+        let length = (q.w * q.w + q.x * q.x + q.y * q.y + q.z * q.z).sqrt();
+        Quaternion { w: q.w / length, x: q.x / length, y: q.y / length, z: q.z / length }
+    }
+
+    fn matrix(&self) -> [[f64; 3]; 3] {
+        let Quaternion { w, x, y, z } = *self;
+        [
+            [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - w * z), 2.0 * (x * z + w * y)],
+            [2.0 * (x * y + w * z), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - w * x)],
+            [2.0 * (x * z - w * y), 2.0 * (y * z + w * x), 1.0 - 2.0 * (x * x + y * y)],
+        ]
+    }
+}
+
+fn multiply(a: &[[f64; 4]; 4], b: &[[f64; 4]; 4]) -> [[f64; 4]; 4] {
+    let mut product = [[0.0; 4]; 4];
+    for row in 0..4 {
+        for column in 0..4 {
+            product[row][column] = (0..4).map(|k| a[row][k] * b[k][column]).sum();
+        }
+    }
+    product
+}
+
+fn apply(m: &[[f64; 4]; 4], v: &Vector3, w: f64) -> Vector3 {
+    Vector3::new(
+        m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z + m[0][3] * w,
+        m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z + m[1][3] * w,
+        m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z + m[2][3] * w,
+    )
+}
+
+//Gauss-Jordan elimination with partial pivoting
+fn invert(m: &[[f64; 4]; 4]) -> Option<[[f64; 4]; 4]> {
+    let mut a = *m;
+    let mut inverse = IDENTITY;
+    for column in 0..4 {
+        let pivot = (column..4).max_by(|&r1, &r2| a[r1][column].abs().partial_cmp(&a[r2][column].abs()).unwrap())?;
+        if a[pivot][column].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(column, pivot);
+        inverse.swap(column, pivot);
+        let scale = 1.0 / a[column][column];
+        for j in 0..4 {
+            a[column][j] *= scale;
+            inverse[column][j] *= scale;
+        }
+        for row in 0..4 {
+            if row != column {
+                let factor = a[row][column];
+                for j in 0..4 {
+                    a[row][j] -= factor * a[column][j];
+                    inverse[row][j] -= factor * inverse[column][j];
+                }
+            }
+        }
+    }
+    Some(inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Transform {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn assert_close(a: &[[f64; 4]; 4], b: &[[f64; 4]; 4]) {
+        for row in 0..4 {
+            for column in 0..4 {
+                assert!((a[row][column] - b[row][column]).abs() < 1e-9, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_the_matrix() {
+        let matrix = [[0.0, -2.0, 0.0, 1.0], [1.0, 0.0, 0.0, 2.0], [0.0, 0.0, 3.0, 3.0], [0.0, 0.0, 0.0, 1.0]];
+        let transform = Transform::new(matrix).unwrap();
+        assert_close(&multiply(&transform.matrix, &transform.inverse), &IDENTITY);
+        assert_close(&multiply(&transform.inverse, &transform.matrix), &IDENTITY);
+        let point = transform.point_to_local(&transform.point_to_world(&Vector3::new(4.0, -5.0, 6.0)));
+        assert!((point.x - 4.0).abs() < 1e-9 && (point.y + 5.0).abs() < 1e-9 && (point.z - 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn inverse_pivots_past_zeros() {
+        let swap = [[0.0, 1.0, 0.0, 0.0], [1.0, 0.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]];
+        assert_close(&invert(&swap).unwrap(), &swap);
+    }
+
+    #[test]
+    fn singular_matrix_is_rejected() {
+        assert!(Transform::new([[1.0, 0.0, 0.0, 0.0], [0.0, 0.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]]).is_err());
+        let result: Result<Transform, _> = serde_json::from_str(r#"{ "scale": [1, 0, 1] }"#);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn moving_transform_starts_and_ends_at_its_keyframes() {
+        let transform = parse(r#"{ "translate": { "x": 1, "y": 2, "z": 3 }, "rotate": { "x": 10, "y": 20, "z": 30 }, "scale": [1, 2, 3],
+            "end": { "translate": { "x": -1, "y": 0, "z": 5 }, "rotate": { "x": 40, "y": -50, "z": 60 }, "scale": 2 } }"#);
+        let start = parse(r#"{ "translate": { "x": 1, "y": 2, "z": 3 }, "rotate": { "x": 10, "y": 20, "z": 30 }, "scale": [1, 2, 3] }"#);
+        let end = parse(r#"{ "translate": { "x": -1, "y": 0, "z": 5 }, "rotate": { "x": 40, "y": -50, "z": 60 }, "scale": 2 }"#);
+        assert_close(&transform.at(0.0).matrix, &start.matrix);
+        assert_close(&transform.at(1.0).matrix, &end.matrix);
+        let halfway = transform.at(0.5);
+        assert_close(&multiply(&halfway.matrix, &halfway.inverse), &IDENTITY);
+    }
+
+    #[test]
+    fn half_turn_keeps_its_shape() {
+        let transform = parse(r#"{ "end": { "rotate": { "x": 0, "y": 180, "z": 0 } } }"#).at(0.5);
+        let x = transform.direction_to_world(&Vector3::new(1.0, 0.0, 0.0));
+        assert!((x.length() - 1.0).abs() < 1e-9);
+        assert!(x.x.abs() < 1e-9 && x.y.abs() < 1e-9); //a quarter turn about y, not squashed flat
+    }
+
+    #[test]
+    fn moving_matrix_is_rejected() {
+        let result: Result<Transform, _> = serde_json::from_str(r#"{ "matrix": [[1, 0, 0, 0], [0, 1, 0, 0], [0, 0, 1, 0], [0, 0, 0, 1]], "end": { "translate": { "x": 1, "y": 0, "z": 0 } } }"#);
+        assert!(result.is_err());
+    }
+}